@@ -57,6 +57,15 @@ impl<Event: EventTrait> Sender<Event> {
     pub async fn send(&self, event: Event) {
         self.socket.as_ref().0.push(Data(event));
     }
+
+    /// 队列中尚未取出的事件数
+    pub fn len(&self) -> usize {
+        self.socket.as_ref().0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 impl<Event: EventTrait> Receiver<Event> {
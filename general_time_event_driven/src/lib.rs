@@ -1,5 +1,3 @@
-#![feature(associated_type_defaults)]
-
 pub mod event_queue;
 pub mod types;
 pub mod widget_queue;
@@ -12,4 +10,204 @@ mod tests {
     use crate::types::*;
     use std::{any::Any, sync::Arc};
     use tokio::sync::{broadcast, mpsc};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    struct Property;
+
+    impl EventTypeTrait for Property {}
+    impl WorkerPropertyTrait for Property {}
+
+    #[derive(Debug, PartialEq)]
+    struct Hit(u32);
+
+    impl ReturnTypeTrait for Hit {
+        type Missed = MissRecord<&'static str, Property, i64>;
+    }
+
+    struct TestEvent(i64);
+
+    impl EventTrait for TestEvent {
+        type TimestampType = i64;
+        type EventType = Property;
+        type WorkerProperty = Property;
+        type ReturnType = Hit;
+        fn get_event_property(&self) -> Self::EventType {
+            Property
+        }
+        fn time_stamp(&self) -> Self::TimestampType {
+            self.0
+        }
+    }
+
+    // 第一次判定后保持存活，第二次判定后销毁
+    struct TwoStepWidget {
+        time_stamp: i64,
+        hits: u32,
+    }
+
+    impl WidgetTrait for TwoStepWidget {
+        type Event = TestEvent;
+        fn get_worker_property(&self) -> Property {
+            Property
+        }
+        fn judge(&mut self, _: &TestEvent) -> RuntimeState<Hit> {
+            self.hits += 1;
+            if self.hits == 1 {
+                RuntimeState::Pending(RuntimeEvent::Some(Hit(self.hits)))
+            } else {
+                RuntimeState::Ready(RuntimeEvent::Some(Hit(self.hits)))
+            }
+        }
+        fn time_stamp(&self) -> i64 {
+            self.time_stamp
+        }
+    }
+
+    // 事件晚于时间戳5以上即错过
+    struct ExpiringWidget {
+        name: &'static str,
+        time_stamp: i64,
+    }
+
+    impl WidgetTrait for ExpiringWidget {
+        type Event = TestEvent;
+        fn get_worker_property(&self) -> Property {
+            Property
+        }
+        fn judge(&mut self, event: &TestEvent) -> RuntimeState<Hit> {
+            if event.0 > self.time_stamp + 5 {
+                RuntimeState::Ready(RuntimeEvent::Missed(self.miss_record(self.name)))
+            } else {
+                RuntimeState::Ready(RuntimeEvent::Some(Hit(0)))
+            }
+        }
+        fn time_stamp(&self) -> i64 {
+            self.time_stamp
+        }
+    }
+
+    #[tokio::test]
+    async fn missed_event_identifies_widget() {
+        let (rt_sndr, mut rt_rcvr) = mpsc::channel(10);
+        let (sndr, _pool) = worker_pool::WorkerPool::build(
+            vec![(
+                Property,
+                WorkerMode::ProcessMultiTimes,
+                BuildBoxedEventSelector(|_: &Property| true),
+            )],
+            vec![
+                ExpiringWidget {
+                    name: "early",
+                    time_stamp: 0,
+                },
+                ExpiringWidget {
+                    name: "late",
+                    time_stamp: 8,
+                },
+            ],
+            rt_sndr,
+        )
+        .await;
+
+        sndr.send(TestEvent(10)).await;
+        match rt_rcvr.recv().await {
+            Some(RuntimeEvent::Missed(record)) => assert_eq!(
+                record,
+                MissRecord {
+                    id: "early",
+                    worker_property: Property,
+                    time_stamp: 0,
+                }
+            ),
+            other => panic!("unexpected {other:?}"),
+        }
+        assert!(matches!(
+            rt_rcvr.recv().await,
+            Some(RuntimeEvent::Some(Hit(0)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn pending_widget_stays_on_worker() {
+        let (rt_sndr, mut rt_rcvr) = mpsc::channel(10);
+        let (sndr, _pool) = worker_pool::WorkerPool::build(
+            vec![(
+                Property,
+                WorkerMode::ProcessOnce,
+                BuildBoxedEventSelector(|_: &Property| true),
+            )],
+            vec![TwoStepWidget {
+                time_stamp: 0,
+                hits: 0,
+            }],
+            rt_sndr,
+        )
+        .await;
+
+        // 早于组件时间戳的事件不会被判定
+        sndr.send(TestEvent(-10)).await;
+        sndr.send(TestEvent(10)).await;
+        sndr.send(TestEvent(20)).await;
+        for expected in 1..=2 {
+            match rt_rcvr.recv().await {
+                Some(RuntimeEvent::Some(hit)) => assert_eq!(hit, Hit(expected)),
+                other => panic!("unexpected {other:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn builds_more_widgets_than_channel_capacity() {
+        // 单个线程的组件数超过组件通道的容量
+        let count = 2500;
+        let (rt_sndr, mut rt_rcvr) = mpsc::channel(10);
+        let widgets = (0..count)
+            .map(|time_stamp| ExpiringWidget {
+                name: "tap",
+                time_stamp,
+            })
+            .collect();
+        let (sndr, _pool) = worker_pool::WorkerPool::build(
+            vec![(
+                Property,
+                WorkerMode::ProcessOnce,
+                BuildBoxedEventSelector(|_: &Property| true),
+            )],
+            widgets,
+            rt_sndr,
+        )
+        .await;
+
+        sndr.send(TestEvent(count - 1)).await;
+        assert!(matches!(
+            rt_rcvr.recv().await,
+            Some(RuntimeEvent::Missed(MissRecord { time_stamp: 0, .. }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn lagging_worker_keeps_running() {
+        let (rt_sndr, mut rt_rcvr) = mpsc::channel(10);
+        let (sndr, _pool) = worker_pool::WorkerPool::build(
+            vec![(
+                Property,
+                WorkerMode::ProcessMultiTimes,
+                BuildBoxedEventSelector(|_: &Property| true),
+            )],
+            vec![ExpiringWidget {
+                name: "tap",
+                time_stamp: 5000,
+            }],
+            rt_sndr,
+        )
+        .await;
+
+        // 工作线程运行前送入超过广播容量的事件
+        for time_stamp in 0..3000 {
+            sndr.send(TestEvent(time_stamp)).await;
+        }
+        sndr.send(TestEvent(5000)).await;
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), rt_rcvr.recv()).await;
+        assert!(matches!(result, Ok(Some(RuntimeEvent::Some(Hit(0))))));
+    }
 }
@@ -1,8 +1,7 @@
-use tokio::time::{Duration, sleep};
+#![allow(dead_code)]
 
 use general_time_event_driven::{types::*, worker_pool::*};
-use std::{any::Any, sync::Arc};
-use tokio::sync::{broadcast, mpsc};
+use tokio::sync::mpsc;
 
 type TimeStamp = i64;
 
@@ -39,7 +38,9 @@ struct TestRtV {
     judgement: Judgement,
 }
 
-impl ReturnTypeTrait for TestRtV {}
+impl ReturnTypeTrait for TestRtV {
+    type Missed = MissRecord<usize, TestWorkerType, TimeStamp>;
+}
 
 // 事件模块
 struct TestEvent {
@@ -97,7 +98,7 @@ impl WidgetTrait for TestWidget {
                 judgement: Judgement::Good,
             }))
         } else {
-            RuntimeState::Ready(RuntimeEvent::Missed)
+            RuntimeState::Ready(RuntimeEvent::Missed(self.miss_record(self.id)))
         }
     }
 }
@@ -129,7 +130,7 @@ async fn test_worker_pool_process() {
     let widget_list = vec![widget];
 
     let (rt_evnt_sndr, mut rcvr) = mpsc::channel(100);
-    let (sndr, hndl) = WorkerPool::build(wrk_ppty, widget_list, rt_evnt_sndr).await;
+    let (sndr, _hndl) = WorkerPool::build(wrk_ppty, widget_list, rt_evnt_sndr).await;
 
     let event = TestEvent {
         time_stamp: 1024,
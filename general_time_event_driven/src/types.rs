@@ -11,8 +11,44 @@ pub trait WidgetTrait: Send + Sync {
         event: &Self::Event,
     ) -> RuntimeState<<<Self as WidgetTrait>::Event as EventTrait>::ReturnType>;
     fn time_stamp(&self) -> <Self::Event as EventTrait>::TimestampType;
+
+    /// 错过记录中的时刻，默认为组件的时间戳
+    ///
+    /// 时间戳为开始接受处理的时刻时，可改为计划处理的时刻
+    fn miss_time(&self) -> <Self::Event as EventTrait>::TimestampType {
+        self.time_stamp()
+    }
+
+    /// 以组件自身的工作属性与 `miss_time` 生成错过记录
+    fn miss_record<Id>(&self, id: Id) -> MissRecordOf<Self, Id>
+    where
+        Self: Sized,
+    {
+        MissRecord {
+            id,
+            worker_property: self.get_worker_property(),
+            time_stamp: self.miss_time(),
+        }
+    }
 }
 
+/// 错过记录
+///
+/// 标识被错过的组件：组件的标识、所属的工作属性，以及组件的 `miss_time`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissRecord<Id, WorkerProperty, TimestampType> {
+    pub id: Id,
+    pub worker_property: WorkerProperty,
+    pub time_stamp: TimestampType,
+}
+
+/// 组件对应的错过记录类型
+pub type MissRecordOf<Widget, Id> = MissRecord<
+    Id,
+    <<Widget as WidgetTrait>::Event as EventTrait>::WorkerProperty,
+    <<Widget as WidgetTrait>::Event as EventTrait>::TimestampType,
+>;
+
 impl<Event: EventTrait> PartialEq for dyn WidgetTrait<Event = Event> {
     fn eq(&self, other: &Self) -> bool {
         self.time_stamp() == other.time_stamp()
@@ -116,8 +152,17 @@ impl<
 
 /// 返回值Trait
 ///
-/// 作为通用返回值的标记Trait
-pub trait ReturnTypeTrait: Send + Sync {}
+/// 作为通用返回值的标记Trait，`Missed` 为错过处理时携带的信息，通常为 `MissRecord`
+pub trait ReturnTypeTrait: Send + Sync {
+    type Missed: Send + Sync + std::fmt::Debug;
+
+    /// 是否为不代表任何处理结果的空返回值
+    ///
+    /// 单次处理模式下，空返回值不会让事件停止传递给后续组件
+    fn is_blank(&self) -> bool {
+        false
+    }
+}
 
 /// Box智能指针包装的静态返回值类型
 pub type BoxedReturnType<ReturnType> = Box<ReturnType>;
@@ -139,8 +184,8 @@ pub enum RuntimeState<ReturnType: ReturnTypeTrait> {
 pub enum RuntimeEvent<ReturnType: ReturnTypeTrait> {
     /// 包含返回值的事件
     Some(ReturnType),
-    /// 错过处理的事件
-    Missed,
+    /// 错过处理的事件，携带被错过组件的信息
+    Missed(ReturnType::Missed),
 }
 
 /// 事件类型Trait
@@ -163,13 +208,17 @@ pub enum WorkerMode {
     ProcessMultiTimes,
 }
 
+/// Box包装的事件类型判断闭包
+pub type BoxedEventSelector<EventType> = Box<dyn Fn(&EventType) -> bool + Send + Sync>;
+
 /// 制造事件类型判断的闭包
+#[allow(non_snake_case)]
 pub fn BuildBoxedEventSelector<
     EventType: EventTypeTrait,
     F: Fn(&EventType) -> bool + Send + Sync + 'static,
 >(
     f: F,
-) -> Box<dyn Fn(&EventType) -> bool + Send + Sync> {
-    let event_selector: Box<dyn Fn(&EventType) -> bool + Send + Sync> = Box::new(f);
+) -> BoxedEventSelector<EventType> {
+    let event_selector: BoxedEventSelector<EventType> = Box::new(f);
     event_selector
 }
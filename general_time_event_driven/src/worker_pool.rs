@@ -10,85 +10,75 @@ const BUFFER_LENGTH: usize = 1000;
 
 struct WorkerHandle<Event: EventTrait, Widget: WidgetTrait<Event = Event>> {
     widget_sender: mpsc::Sender<Widget>,
-    process_handle: JoinHandle<()>,
+    _process_handle: JoinHandle<()>,
 }
 
 impl<Event: EventTrait + 'static, Widget: WidgetTrait<Event = Event> + 'static>
     WorkerHandle<Event, Widget>
 {
     fn new(
+        worker_property: Event::WorkerProperty,
         mut event_receiver: broadcast::Receiver<Arc<Widget::Event>>,
         event_worker_mode: WorkerMode,
         runtime_event_sender: mpsc::Sender<
             RuntimeEvent<<<Widget as WidgetTrait>::Event as EventTrait>::ReturnType>,
         >,
         runtime_widget_sender_pre: mpsc::Sender<Widget>,
-        event_selector: Box<
-            dyn Fn(&<Widget::Event as EventTrait>::EventType) -> bool + Send + Sync,
-        >,
+        event_selector: BoxedEventSelector<<Widget::Event as EventTrait>::EventType>,
+        initial_widgets: Vec<Widget>,
     ) -> Self {
         let (widget_sender, mut widget_receiver) = mpsc::channel(BUFFER_LENGTH);
         Self {
             widget_sender,
-            process_handle: tokio::spawn(async move {
+            _process_handle: tokio::spawn(async move {
+                // 初始组件不经过通道，数量不受通道容量限制
                 let mut widget_heap = WidgetHeap::new();
-                while let Ok(event) = event_receiver.recv().await {
+                for widget in initial_widgets {
+                    widget_heap.push(widget);
+                }
+                loop {
+                    let event = match event_receiver.recv().await {
+                        Ok(event) => event,
+                        // 落后超过广播容量时，被覆盖的事件已无法取回，从最早仍保留的事件继续
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            eprintln!("工作线程落后，跳过 {skipped} 个事件");
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
                     while !widget_receiver.is_empty() {
                         widget_heap.push(widget_receiver.recv().await.unwrap());
                     }
-                    if event_selector(&event.as_ref().get_event_property()) {
-                        match event_worker_mode {
-                            WorkerMode::ProcessOnce => {
-                                let mut flg = true;
-                                while !widget_heap.is_empty() && flg {
-                                    if let Some(widget) = widget_heap.peek()
-                                        && widget.time_stamp() <= event.time_stamp()
-                                    {
-                                        let mut widget = widget_heap.pop().unwrap();
-                                        let runtime_state = widget.judge(event.as_ref());
-                                        match runtime_state {
-                                            RuntimeState::Pending(runtime_event) => {
-                                                if let RuntimeEvent::Some(_) = runtime_event {
-                                                    flg = false;
-                                                }
-                                                let _ =
-                                                    runtime_event_sender.send(runtime_event).await;
-                                                let _ =
-                                                    runtime_widget_sender_pre.send(widget).await;
-                                            }
-                                            RuntimeState::Ready(runtime_event) => {
-                                                if let RuntimeEvent::Some(_) = runtime_event {
-                                                    flg = false;
-                                                }
-                                                let _ =
-                                                    runtime_event_sender.send(runtime_event).await;
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            WorkerMode::ProcessMultiTimes => {
-                                while !widget_heap.is_empty() {
-                                    if let Some(widget) = widget_heap.peek()
-                                        && widget.time_stamp() <= event.time_stamp()
-                                    {
-                                        let mut widget = widget_heap.pop().unwrap();
-                                        let runtime_state = widget.judge(event.as_ref());
-                                        match runtime_state {
-                                            RuntimeState::Pending(runtime_event) => {
-                                                let _ =
-                                                    runtime_event_sender.send(runtime_event).await;
-                                                let _ =
-                                                    runtime_widget_sender_pre.send(widget).await;
-                                            }
-                                            RuntimeState::Ready(runtime_event) => {
-                                                let _ =
-                                                    runtime_event_sender.send(runtime_event).await;
-                                            }
-                                        }
-                                    }
-                                }
-                            }
+                    if !event_selector(&event.as_ref().get_event_property()) {
+                        continue;
+                    }
+                    // 本次事件中未完成判定的组件，处理结束后再放回
+                    let mut pending_widgets = Vec::new();
+                    while let Some(widget) = widget_heap.peek()
+                        && widget.time_stamp() <= event.time_stamp()
+                    {
+                        let mut widget = widget_heap.pop().unwrap();
+                        let runtime_state = widget.judge(event.as_ref());
+                        let (runtime_event, is_pending) = match runtime_state {
+                            RuntimeState::Pending(runtime_event) => (runtime_event, true),
+                            RuntimeState::Ready(runtime_event) => (runtime_event, false),
+                        };
+                        let is_some = matches!(&runtime_event, RuntimeEvent::Some(value) if !value.is_blank());
+                        let _ = runtime_event_sender.send(runtime_event).await;
+                        if is_pending {
+                            pending_widgets.push(widget);
+                        }
+                        // 单次处理模式下，事件被一个组件接收后即停止
+                        if is_some && matches!(event_worker_mode, WorkerMode::ProcessOnce) {
+                            break;
+                        }
+                    }
+                    for widget in pending_widgets {
+                        // 仍属于本线程的组件直接放回，避免经过路由时错过后续事件
+                        if widget.get_worker_property() == worker_property {
+                            widget_heap.push(widget);
+                        } else {
+                            let _ = runtime_widget_sender_pre.send(widget).await;
                         }
                     }
                 }
@@ -108,7 +98,7 @@ impl<Event: EventTrait + 'static, Widget: WidgetTrait<Event = Event> + 'static>
 pub struct WorkerPool<Event: EventTrait, Widget: WidgetTrait<Event = Event>> {
     // 优先队列线程
     pub input_worker_handle: JoinHandle<()>,
-    _event_broadcast_sender: broadcast::Sender<Arc<Event>>,
+    event_broadcast_sender: broadcast::Sender<Arc<Event>>,
 
     // 哈希表路由线程
     pub widget_router_handle: JoinHandle<()>,
@@ -135,7 +125,7 @@ impl<Event: EventTrait + 'static, Widget: WidgetTrait<Event = Event> + 'static>
         worker_property: Vec<(
             Event::WorkerProperty,
             WorkerMode,
-            Box<dyn Fn(&Event::EventType) -> bool + Send + Sync>,
+            BoxedEventSelector<Event::EventType>,
         )>,
         widgets: Vec<Widget>,
         return_event_sender: mpsc::Sender<RuntimeEvent<Event::ReturnType>>,
@@ -145,17 +135,29 @@ impl<Event: EventTrait + 'static, Widget: WidgetTrait<Event = Event> + 'static>
         // let (runtime_event_sender, runtime_event_receiver) = mpsc::channel(BUFFER_LENGTH);
         let (runtime_widget_sender_pre, mut runtime_widget_receiver_pre) =
             mpsc::channel(BUFFER_LENGTH);
+
+        // 初始组件按工作属性分组，在线程启动时直接放入其组件堆，保证在第一个事件到达前就位
+        let mut initial_widgets: HashMap<Event::WorkerProperty, Vec<Widget>> = HashMap::new();
+        for widget in widgets.into_iter() {
+            initial_widgets
+                .entry(widget.get_worker_property())
+                .or_default()
+                .push(widget);
+        }
         let workers_table = worker_property
             .into_iter()
             .map(|e| {
+                let widgets = initial_widgets.remove(&e.0).unwrap_or_default();
                 (
-                    e.0,
+                    e.0.clone(),
                     WorkerHandle::new(
+                        e.0,
                         event_transmit.subscribe(),
                         e.1,
                         return_event_sender.clone(),
                         runtime_widget_sender_pre.clone(),
                         e.2,
+                        widgets,
                     ),
                 )
             })
@@ -184,18 +186,21 @@ impl<Event: EventTrait + 'static, Widget: WidgetTrait<Event = Event> + 'static>
             }
         });
 
-        for e in widgets.into_iter() {
-            runtime_widget_sender_pre.send(e).await.unwrap()
-        }
-
         (
             event_pipe_sender,
             Self {
                 input_worker_handle,
                 widget_router_handle,
-                _event_broadcast_sender: event_broadcast_sender,
+                event_broadcast_sender,
                 _runtime_widget_sender_pre: runtime_widget_sender_pre,
             },
         )
     }
+
+    /// 已广播但尚未被所有工作线程接收的事件数
+    ///
+    /// 超过广播容量时落后的工作线程会跳过事件，大量发送事件时可据此等待工作线程跟上
+    pub fn backlog(&self) -> usize {
+        self.event_broadcast_sender.len()
+    }
 }
@@ -0,0 +1,158 @@
+use crate::judgement::JudgementProfile;
+use crate::replay::Replay;
+use crate::types::{ChartTime, Event, EventType, Widget, WidgetKind, WkrType};
+
+use std::collections::HashMap;
+
+// 单点按下后保持的时长
+const TAP_HOLD_MS: i64 = 40;
+
+/// 由判定组件生成全部 Critical Perfect 的输入
+///
+/// 按下恰好发生在判定时刻，长条在结束时刻松开；单点按住 `TAP_HOLD_MS`，
+/// 但不超过与同一位置下一个音符间隔的一半。星星轨迹在开始与结束时刻之间依次触摸经过的区域，
+/// 最后一个区域恰好在结束时刻；触摸下一个区域时松开上一个，最后一个区域按住 `TAP_HOLD_MS`。
+/// 返回的事件按时间排序
+pub fn autoplay_events(widgets: &[Widget]) -> Vec<Event> {
+    let mut sorted: Vec<&Widget> = widgets.iter().collect();
+    sorted.sort_by_key(|widget| widget.time_hit);
+
+    // 从后往前生成，以便知道同一位置下一次按下的时刻
+    let mut next_down: HashMap<WkrType, ChartTime> = HashMap::new();
+    let mut events = Vec::new();
+    for widget in sorted.into_iter().rev() {
+        let at = |time_stamp, event_ppty| Event {
+            time_stamp,
+            event_ppty,
+        };
+        let (down, up) = match widget.wkr_ppty {
+            WkrType::Lane(lane) => (EventType::Press(lane), EventType::Release(lane)),
+            WkrType::Sensor(zone) => (EventType::TouchDown(zone), EventType::TouchUp(zone)),
+            WkrType::Slide => {
+                if let WidgetKind::Slide { time_end, path, .. } = &widget.kind {
+                    let step = (*time_end - widget.time_hit) / path.len().max(1) as i32;
+                    let touched_at = |i: usize| match i + 1 == path.len() {
+                        true => *time_end,
+                        false => widget.time_hit + step * (i as i32 + 1),
+                    };
+                    for (i, zone) in path.iter().enumerate() {
+                        let released_at = match i + 1 == path.len() {
+                            true => *time_end + ChartTime::milliseconds(TAP_HOLD_MS),
+                            false => touched_at(i + 1),
+                        };
+                        events.push(at(touched_at(i), EventType::TouchDown(*zone)));
+                        events.push(at(released_at, EventType::TouchUp(*zone)));
+                    }
+                }
+                continue;
+            }
+            WkrType::Wkr0 => continue,
+        };
+        let release = match widget.kind {
+            WidgetKind::Hold { time_end, .. } => time_end,
+            _ => {
+                let held = ChartTime::milliseconds(TAP_HOLD_MS);
+                match next_down.get(&widget.wkr_ppty) {
+                    Some(&next) => widget.time_hit + held.min((next - widget.time_hit) / 2),
+                    None => widget.time_hit + held,
+                }
+            }
+        };
+        events.push(at(widget.time_hit, down));
+        events.push(at(release, up));
+        next_down.insert(widget.wkr_ppty, widget.time_hit);
+    }
+    // 同一时刻先松开再按下
+    events.sort_by_key(|event| (event.time_stamp, event.event_ppty.is_down()));
+    events
+}
+
+/// 自动游玩的回放，离线时由 `Replay::play` 判定，游玩时由 `start_replay_feed` 送入判定线程
+pub fn autoplay_replay(
+    widgets: &[Widget],
+    chart_hash: String,
+    profile: JudgementProfile,
+) -> Replay {
+    let mut replay = Replay::new(chart_hash, profile);
+    replay.mods.push("autoplay".to_string());
+    for event in autoplay_events(widgets) {
+        replay.record(&event);
+    }
+    replay
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::Zone;
+    use crate::types::{HoldState, Judgement, ms};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn autoplay_is_all_critical_perfect() {
+        let profile = JudgementProfile::maimai_dx();
+        let shared = Arc::new(profile.clone());
+        let widgets = || {
+            vec![
+                Widget::tap_at(0, 1000, WkrType::Lane(0), &shared),
+                // 间隔很短的连打
+                Widget::tap_at(1, 1030, WkrType::Lane(0), &shared),
+                Widget::at(
+                    2,
+                    1500,
+                    WkrType::Lane(1),
+                    WidgetKind::Hold {
+                        time_end: ms(2500),
+                        state: HoldState::Waiting,
+                    },
+                    &shared,
+                ),
+                Widget::tap_at(3, 2000, WkrType::Sensor(Zone::C), &shared),
+                Widget::at(
+                    4,
+                    3000,
+                    WkrType::Slide,
+                    WidgetKind::Slide {
+                        time_end: ms(3600),
+                        path: vec![Zone::A(1), Zone::A(2), Zone::A(3)],
+                        progress: 0,
+                    },
+                    &shared,
+                ),
+            ]
+        };
+
+        let events = autoplay_events(&widgets());
+        assert_eq!(events.len(), 14);
+        assert!(events.is_sorted_by_key(|event| event.time_stamp));
+        // 第一个单点在下一个按下之前松开
+        assert_eq!(events[1].time_stamp, ms(1015));
+        assert_eq!(events[1].event_ppty, EventType::Release(0));
+        // 星星经过的区域都会松开
+        let slide: Vec<_> = events
+            .iter()
+            .filter(|event| event.time_stamp >= ms(3000))
+            .map(|event| (event.time_stamp.num_milliseconds(), event.event_ppty))
+            .collect();
+        assert_eq!(
+            slide,
+            [
+                (3200, EventType::TouchDown(Zone::A(1))),
+                (3400, EventType::TouchUp(Zone::A(1))),
+                (3400, EventType::TouchDown(Zone::A(2))),
+                (3600, EventType::TouchUp(Zone::A(2))),
+                (3600, EventType::TouchDown(Zone::A(3))),
+                (3640, EventType::TouchUp(Zone::A(3))),
+            ]
+        );
+
+        let results = autoplay_replay(&widgets(), String::new(), profile)
+            .play(widgets())
+            .await;
+        assert_eq!(results.len(), 6);
+        for result in results {
+            assert_eq!(result.judgement, Some(Judgement::CriticalPerfect));
+            assert_eq!(result.offset_us, Some(0));
+        }
+    }
+}
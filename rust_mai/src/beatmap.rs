@@ -0,0 +1,317 @@
+use crate::judgement::JudgementProfile;
+use crate::scroll::ScrollMap;
+use crate::types::{ChartTime, HoldState, Widget, WidgetForDisplay, WidgetKind, WkrType};
+use serde::{Deserialize, Serialize};
+
+use std::sync::Arc;
+
+/// osu! 谱面模型
+///
+/// 对应 .osu 文件中与游玩相关的各个段落，不包含任何运行时对象
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Beatmap {
+    pub format_version: u32,
+    pub general: General,
+    pub metadata: Metadata,
+    pub difficulty: Difficulty,
+    pub events: Vec<BeatmapEvent>,
+    pub timing_points: Vec<TimingPoint>,
+    pub hit_objects: Vec<HitObject>,
+}
+
+/// [General] 段
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct General {
+    pub audio_filename: String,
+    pub audio_lead_in: i64,
+    pub preview_time: i64,
+    pub countdown: i32,
+    pub sample_set: String,
+    pub stack_leniency: f64,
+    pub mode: u8,
+    pub letterbox_in_breaks: bool,
+    pub special_style: bool,
+    pub widescreen_storyboard: bool,
+    /// 未建模的键值对，按原顺序保留
+    pub extra: Vec<(String, String)>,
+}
+
+impl Default for General {
+    fn default() -> Self {
+        Self {
+            audio_filename: String::new(),
+            audio_lead_in: 0,
+            preview_time: -1,
+            countdown: 1,
+            sample_set: "Normal".to_string(),
+            stack_leniency: 0.7,
+            mode: 0,
+            letterbox_in_breaks: false,
+            special_style: false,
+            widescreen_storyboard: false,
+            extra: Vec::new(),
+        }
+    }
+}
+
+/// [Metadata] 段
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub title: String,
+    pub title_unicode: String,
+    pub artist: String,
+    pub artist_unicode: String,
+    pub creator: String,
+    pub version: String,
+    pub source: String,
+    pub tags: Vec<String>,
+    pub beatmap_id: Option<i64>,
+    pub beatmap_set_id: Option<i64>,
+}
+
+/// [Difficulty] 段
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Difficulty {
+    pub hp_drain_rate: f64,
+    pub circle_size: f64,
+    pub overall_difficulty: f64,
+    pub approach_rate: f64,
+    pub slider_multiplier: f64,
+    pub slider_tick_rate: f64,
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Self {
+            hp_drain_rate: 5.,
+            circle_size: 5.,
+            overall_difficulty: 5.,
+            approach_rate: 5.,
+            slider_multiplier: 1.4,
+            slider_tick_rate: 1.,
+        }
+    }
+}
+
+/// [Events] 段中的一行
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BeatmapEvent {
+    /// 背景图片
+    Background { filename: String, x: i32, y: i32 },
+    /// 背景视频
+    Video {
+        start_time: i64,
+        filename: String,
+        x: i32,
+        y: i32,
+    },
+    /// 休息段
+    Break { start_time: i64, end_time: i64 },
+    /// 故事板等未建模的行，原样保留
+    Other(String),
+}
+
+/// [TimingPoints] 段中的一个时间点
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimingPoint {
+    pub time: f64,
+    pub beat_length: f64,
+    pub meter: i32,
+    pub sample_set: i32,
+    pub sample_index: i32,
+    pub volume: i32,
+    pub uninherited: bool,
+    pub effects: i32,
+}
+
+impl TimingPoint {
+    /// 非继承时间点的BPM
+    pub fn bpm(&self) -> Option<f64> {
+        (self.uninherited && self.beat_length > 0.).then(|| 60_000. / self.beat_length)
+    }
+
+    /// 继承时间点的流速倍率
+    pub fn sv_multiplier(&self) -> Option<f64> {
+        (!self.uninherited && self.beat_length < 0.).then(|| -100. / self.beat_length)
+    }
+}
+
+/// 击打音效设置
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HitSample {
+    pub normal_set: i32,
+    pub addition_set: i32,
+    pub index: i32,
+    pub volume: i32,
+    pub filename: String,
+}
+
+/// 物件类型
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum HitObjectKind {
+    Circle,
+    /// 滑条参数在 mania 中无意义，原样保留
+    Slider {
+        params: String,
+    },
+    Spinner {
+        end_time: i64,
+    },
+    Hold {
+        end_time: i64,
+    },
+}
+
+/// [HitObjects] 段中的一个物件
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HitObject {
+    pub x: i32,
+    pub y: i32,
+    pub time: i64,
+    pub kind: HitObjectKind,
+    pub new_combo: bool,
+    pub combo_skip: u8,
+    pub hit_sound: i32,
+    pub hit_sample: HitSample,
+}
+
+impl HitObject {
+    /// 还原 .osu 中的类型位
+    pub fn type_bits(&self) -> i32 {
+        let kind_bit = match self.kind {
+            HitObjectKind::Circle => 1,
+            HitObjectKind::Slider { .. } => 2,
+            HitObjectKind::Spinner { .. } => 8,
+            HitObjectKind::Hold { .. } => 128,
+        };
+        kind_bit | (self.new_combo as i32) << 2 | (self.combo_skip as i32 & 0b111) << 4
+    }
+}
+
+impl Beatmap {
+    /// mania 轨道数，由 CircleSize 决定，范围为 1–18
+    ///
+    /// 只有 1–10 键有内置的默认键位，11 键以上须先用 `bind_keys` 写入按键配置
+    pub fn key_count(&self) -> u8 {
+        self.difficulty.circle_size.round().clamp(1., 18.) as u8
+    }
+
+    /// 按 osu!mania 的规则由x坐标计算轨道
+    pub fn column_of(&self, x: i32) -> u8 {
+        let key_count = self.key_count() as i32;
+        (x * key_count / 512).clamp(0, key_count - 1) as u8
+    }
+
+    /// 背景图片文件名
+    pub fn background(&self) -> Option<&str> {
+        self.events.iter().find_map(|e| match e {
+            BeatmapEvent::Background { filename, .. } => Some(filename.as_str()),
+            _ => None,
+        })
+    }
+
+    /// 由时间点构建流速映射
+    pub fn scroll_map(&self) -> ScrollMap {
+        let end_time = self
+            .hit_objects
+            .iter()
+            .map(|o| match o.kind {
+                HitObjectKind::Hold { end_time } | HitObjectKind::Spinner { end_time } => end_time,
+                _ => o.time,
+            })
+            .max()
+            .unwrap_or(0);
+        ScrollMap::new(&self.timing_points, end_time as f64)
+    }
+
+    /// 由 OverallDifficulty 决定的 osu!mania 判定
+    pub fn judgement_profile(&self) -> JudgementProfile {
+        JudgementProfile::osu_mania(self.difficulty.overall_difficulty)
+    }
+
+    /// 将谱面转换为判定组件与渲染组件
+    ///
+    /// 所有时间均为谱面时间，判定组件共享 `profile`。渲染组件的位置由流速映射积分得到，
+    /// `scroll_speed` 为每单位卷轴位置对应的像素数，`screen_height / scroll_speed`
+    /// 即音符在屏幕上经过的卷轴距离
+    pub fn build_widgets(
+        &self,
+        profile: &Arc<JudgementProfile>,
+        screen_height: f64,
+        scroll_speed: f64,
+    ) -> (Vec<Widget>, Vec<WidgetForDisplay>) {
+        let mut widgets = Vec::new();
+        let mut widgets_for_display = Vec::new();
+        let mut id_counter = 0;
+        let scroll_map = self.scroll_map();
+        let approach_distance = screen_height / scroll_speed;
+
+        for object in &self.hit_objects {
+            // 只处理普通点击和长条
+            if !matches!(
+                object.kind,
+                HitObjectKind::Circle | HitObjectKind::Hold { .. }
+            ) {
+                continue;
+            }
+
+            // 将x坐标映射到WkrType
+            let wkr_type = WkrType::Lane(self.column_of(object.x));
+
+            // 计算时间戳
+            let hit_time = ChartTime::milliseconds(object.time);
+
+            // 计算卷轴位置与显示时间（音符进入屏幕的时刻）
+            let position = scroll_map.position_at(object.time as f64);
+            let display_ms = scroll_map.time_at(position - approach_distance);
+            let display_time = ChartTime::microseconds((display_ms * 1000.) as i64);
+
+            // 长条尾部时间
+            let (time_end, position_end) = match object.kind {
+                HitObjectKind::Hold { end_time } => (
+                    Some(ChartTime::milliseconds(end_time)),
+                    Some(scroll_map.position_at(end_time as f64)),
+                ),
+                _ => (None, None),
+            };
+
+            let widget = Widget::new(
+                id_counter,
+                hit_time,
+                wkr_type,
+                match time_end {
+                    Some(time_end) => WidgetKind::Hold {
+                        time_end,
+                        state: HoldState::Waiting,
+                    },
+                    None => WidgetKind::Tap,
+                },
+                false,
+                Arc::clone(profile),
+            );
+            let widget_time = widget.time_stamp;
+            widgets.push(widget);
+
+            widgets_for_display.push(WidgetForDisplay {
+                id: id_counter,
+                time_stamp_general: if widget_time < display_time {
+                    widget_time
+                } else {
+                    display_time
+                },
+                time_stamp_display: display_time,
+                time_hit: hit_time,
+                time_end,
+                position,
+                position_end,
+                place: wkr_type,
+                is_break: false,
+                deleted: false,
+            });
+
+            id_counter += 1;
+        }
+
+        (widgets, widgets_for_display)
+    }
+}
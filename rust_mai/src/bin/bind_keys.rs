@@ -0,0 +1,33 @@
+use rust_mai::dev_read::AsyncKeyboardListener;
+use rust_mai::keybind::{KeyBindings, bind_interactively};
+
+// 交互式设置按键：`bind_keys [键数] [配置文件]`，配置文件默认为 keybindings.toml
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let key_count = args.next().and_then(|s| s.parse().ok()).unwrap_or(4);
+    let path = args
+        .next()
+        .unwrap_or_else(|| "keybindings.toml".to_string());
+
+    let mut bindings = KeyBindings::load(&path).expect("按键配置读取失败");
+    let current = bindings.lanes(key_count);
+    println!("当前 {key_count} 键键位: {:?}", current.lanes);
+    println!("每条轨道可按下多个按键，回车结束；直接回车或 Esc 保留原有按键");
+    if !current.unbound().is_empty() {
+        println!("{key_count} 键没有默认键位，未绑定的轨道必须按下至少一个按键");
+    }
+
+    let (_listener, mut rx) = AsyncKeyboardListener::new()
+        .await
+        .expect("无法读取输入设备");
+    let lanes = bind_interactively(&mut rx, &current, |lane| {
+        println!("请按下第 {} 轨道的按键", lane + 1)
+    })
+    .await;
+
+    println!("新的键位: {:?}", lanes.lanes);
+    bindings.set(lanes).expect("按键冲突");
+    bindings.save(&path).expect("按键配置写入失败");
+    println!("已保存到 {path}");
+}
@@ -0,0 +1,238 @@
+use rust_mai::autoplay::autoplay_replay;
+use rust_mai::beatmap::Beatmap;
+use rust_mai::judgement::JudgementProfile;
+use rust_mai::osr::Osr;
+use rust_mai::osz::Osz;
+use rust_mai::parser::parse_osu_file;
+use rust_mai::replay::{NoteResult, Replay, ReplayFrame, chart_hash};
+use rust_mai::score::{ScoreSummary, Scorer, rule_by_name};
+use rust_mai::simai::{SimaiChart, parse_maidata_file};
+use rust_mai::types::Widget;
+
+use serde::Serialize;
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::Arc;
+
+const USAGE: &str = "用法: headless <谱面.osu|谱面.osz|maidata.txt> (--replay <回放.mrp|回放.json|回放.osr> | --autoplay | --script <事件.json>) [--difficulty <难度文件名|inote编号>] [--score v1|v2|maimai] [--out <结果.json>]";
+
+// 判定结果的来源
+enum Source {
+    Replay(String),
+    Autoplay,
+    // JSON 格式的 `ReplayFrame` 列表
+    Script(String),
+}
+
+struct Args {
+    chart: String,
+    difficulty: Option<String>,
+    source: Source,
+    // 未指定时使用回放记录的规则，再按谱面种类选择
+    score: Option<String>,
+    out: Option<String>,
+}
+
+// 判定的谱面
+enum Chart {
+    Osu(Box<Beatmap>),
+    Maimai(SimaiChart),
+}
+
+impl Chart {
+    fn hash(&self) -> String {
+        match self {
+            Chart::Osu(beatmap) => chart_hash(beatmap),
+            Chart::Maimai(chart) => chart_hash(chart),
+        }
+    }
+
+    fn judgement_profile(&self) -> JudgementProfile {
+        match self {
+            Chart::Osu(beatmap) => beatmap.judgement_profile(),
+            Chart::Maimai(_) => JudgementProfile::maimai_dx(),
+        }
+    }
+
+    fn default_score(&self) -> &'static str {
+        match self {
+            Chart::Osu(_) => "v1",
+            Chart::Maimai(_) => "maimai",
+        }
+    }
+
+    fn widgets(&self, profile: &JudgementProfile) -> Vec<Widget> {
+        let profile = Arc::new(profile.clone());
+        match self {
+            Chart::Osu(beatmap) => beatmap.build_widgets(&profile, 0., 1.).0,
+            Chart::Maimai(chart) => chart.build_widgets(&profile, 0., 1.).0,
+        }
+    }
+}
+
+// 无头运行的结果
+#[derive(Serialize)]
+struct Report {
+    chart_hash: String,
+    source: String,
+    replay_matches_chart: bool,
+    results: Vec<NoteResult>,
+    summary: ScoreSummary,
+}
+
+fn fail(message: impl Display) -> ! {
+    eprintln!("{message}");
+    std::process::exit(2);
+}
+
+fn parse_args() -> Args {
+    let mut args = std::env::args().skip(1);
+    let mut chart = None;
+    let mut difficulty = None;
+    let mut source = None;
+    let mut score = None;
+    let mut out = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(USAGE));
+        match arg.as_str() {
+            "--replay" => source = Some(Source::Replay(value())),
+            "--autoplay" => source = Some(Source::Autoplay),
+            "--script" => source = Some(Source::Script(value())),
+            "--difficulty" => difficulty = Some(value()),
+            "--score" => score = Some(value()),
+            "--out" => out = Some(value()),
+            _ if chart.is_none() && !arg.starts_with("--") => chart = Some(arg),
+            _ => fail(USAGE),
+        }
+    }
+    match (chart, source) {
+        (Some(chart), Some(source)) => Args {
+            chart,
+            difficulty,
+            source,
+            score,
+            out,
+        },
+        _ => fail(USAGE),
+    }
+}
+
+// maidata.txt，或以 `&` 字段开头的 .txt 文件
+fn is_maidata(path: &str) -> bool {
+    let name = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    name == "maidata.txt"
+        || name.ends_with(".txt")
+            && std::fs::read_to_string(path).is_ok_and(|text| {
+                text.trim_start_matches('\u{feff}')
+                    .trim_start()
+                    .starts_with('&')
+            })
+}
+
+fn load_chart(path: &str, difficulty: Option<String>) -> Chart {
+    if is_maidata(path) {
+        let mut maidata = parse_maidata_file(path).unwrap_or_else(|e| fail(format!("{path}: {e}")));
+        // 默认为编号最大的难度
+        let level = match difficulty {
+            Some(level) => level
+                .parse()
+                .unwrap_or_else(|_| fail(format!("难度应为 inote 编号: {level}"))),
+            None => *maidata
+                .charts
+                .keys()
+                .next_back()
+                .unwrap_or_else(|| fail("谱面中没有难度")),
+        };
+        return Chart::Maimai(
+            maidata
+                .charts
+                .remove(&level)
+                .unwrap_or_else(|| fail(format!("谱面中没有难度 {level}"))),
+        );
+    }
+    if !path.to_ascii_lowercase().ends_with(".osz") {
+        return Chart::Osu(Box::new(
+            parse_osu_file(path).unwrap_or_else(|e| fail(format!("{path}: {e}"))),
+        ));
+    }
+    let mut osz = Osz::open(path).unwrap_or_else(|e| fail(format!("{path}: {e}")));
+    let name = difficulty
+        .or_else(|| osz.difficulties().first().cloned())
+        .unwrap_or_else(|| fail("谱面包中没有难度"));
+    Chart::Osu(Box::new(
+        osz.beatmap(&name)
+            .unwrap_or_else(|e| fail(format!("{path}: {e}"))),
+    ))
+}
+
+// 不渲染，以谱面时间驱动判定并输出每个音符的判定与成绩（JSON）
+#[tokio::main]
+async fn main() {
+    let args = parse_args();
+    let chart = load_chart(&args.chart, args.difficulty);
+    let hash = chart.hash();
+    let profile = chart.judgement_profile();
+
+    let (replay, source) = match &args.source {
+        Source::Replay(path) if path.to_ascii_lowercase().ends_with(".osr") => {
+            let Chart::Osu(beatmap) = &chart else {
+                fail("osu! 回放只能用于 osu!mania 谱面");
+            };
+            let osr = Osr::load(path).unwrap_or_else(|e| fail(format!("{path}: {e}")));
+            let replay = osr
+                .to_replay(beatmap.key_count(), hash.clone(), profile)
+                .unwrap_or_else(|e| fail(format!("{path}: {e}")));
+            (replay, format!("osr:{path}"))
+        }
+        Source::Replay(path) => (
+            Replay::load(path).unwrap_or_else(|e| fail(format!("{path}: {e}"))),
+            format!("replay:{path}"),
+        ),
+        Source::Autoplay => (
+            autoplay_replay(&chart.widgets(&profile), hash.clone(), profile),
+            "autoplay".to_string(),
+        ),
+        Source::Script(path) => {
+            let text =
+                std::fs::read_to_string(path).unwrap_or_else(|e| fail(format!("{path}: {e}")));
+            let frames: Vec<ReplayFrame> =
+                serde_json::from_str(&text).unwrap_or_else(|e| fail(format!("{path}: {e}")));
+            let mut replay = Replay::new(hash.clone(), profile);
+            for frame in frames {
+                replay.record(&frame.to_event());
+            }
+            (replay, format!("script:{path}"))
+        }
+    };
+
+    // 计分规则：命令行指定的规则优先，其次为回放记录的规则
+    let score = args
+        .score
+        .or_else(|| replay.settings.get("score").cloned())
+        .unwrap_or_else(|| chart.default_score().to_string());
+    let rule = rule_by_name(&score).unwrap_or_else(|| fail(format!("未知的计分规则 {score}")));
+
+    // 回放按记录的判定配置重现
+    let widgets = chart.widgets(&replay.profile);
+    let mut scorer = Scorer::for_widgets(rule, &widgets);
+    let results = replay.play(widgets).await;
+    for result in &results {
+        scorer.apply(&result.to_event());
+    }
+    let report = Report {
+        replay_matches_chart: replay.chart_hash == hash,
+        chart_hash: hash,
+        source,
+        results,
+        summary: scorer.summary(),
+    };
+
+    let json = serde_json::to_string_pretty(&report).expect("结果可以序列化");
+    match args.out {
+        Some(path) => std::fs::write(&path, json).unwrap_or_else(|e| fail(format!("{path}: {e}"))),
+        None => println!("{json}"),
+    }
+}
@@ -1,30 +1,75 @@
-use chrono::Utc;
-use general_time_event_driven::types::{BuildBoxedEventSelector, RuntimeEvent, WorkerMode};
+use general_time_event_driven::types::RuntimeEvent;
 use general_time_event_driven::worker_pool::WorkerPool;
-use general_time_event_driven::*;
 use macroquad::prelude::*;
-use rust_mai::clk::start_clk;
-use rust_mai::dev_read::start_key_listen;
+use rust_mai::autoplay::autoplay_replay;
+use rust_mai::beatmap::Beatmap;
+use rust_mai::clk::{ClkStats, start_clk};
+use rust_mai::clock::SharedClock;
+use rust_mai::dev_read::{InputLatency, ListenerConfig, start_key_listen};
+use rust_mai::held::HeldLanes;
+use rust_mai::hit_error::{HitErrors, Timing};
+use rust_mai::input::open_backend;
+use rust_mai::judgement::JudgementProfile;
+use rust_mai::keybind::KeyBindings;
+use rust_mai::osz::Osz;
+use rust_mai::replay::{Replay, chart_hash, start_replay_feed};
+use rust_mai::score::{ScoreSummary, Scorer, rule_by_name};
+use rust_mai::session::PlaySession;
 use rust_mai::sliding_window::SlidingWindow;
 use rust_mai::{parser::*, types::*, widget_for_display_queue::*};
-use tokio::sync::mpsc;
-use tokio::time::error::Elapsed;
-use tokio::time::{Duration, Instant};
 
-use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::thread;
 
-use tokio::runtime;
+// 从命令行读取谱面：`main [谱面.osz|谱面.osu] [难度文件名]`，未指定时使用内置的测试谱面
+fn load_beatmap() -> Beatmap {
+    let mut args = std::env::args().skip(1);
+    let path = args
+        .next()
+        .unwrap_or_else(|| env!("CARGO_MANIFEST_DIR").to_string() + "/src/bin/test.txt");
+    if !path.to_ascii_lowercase().ends_with(".osz") {
+        return parse_osu_file(&path).expect("谱面解析失败");
+    }
+    let mut osz = Osz::open(&path).expect("谱面包打开失败");
+    let difficulties = osz.difficulties();
+    println!("可选难度: {difficulties:#?}");
+    let name = args
+        .next()
+        .or_else(|| difficulties.first().cloned())
+        .expect("谱面包中没有难度");
+    osz.beatmap(&name).expect("谱面解析失败")
+}
 
-struct FallingBlock {
-    position: Vec2,
-    size: Vec2,
-    color: Color,
+fn judgement_color(judgement: Judgement) -> Color {
+    match judgement {
+        Judgement::CriticalPerfect => YELLOW,
+        Judgement::Perfect => PINK,
+        Judgement::Great => GREEN,
+        Judgement::Good => SKYBLUE,
+        Judgement::Meh => DARKGRAY,
+    }
 }
 
-struct GroundLine {
-    y: f32,
-    color: Color,
+// 逐行绘制成绩
+fn draw_summary(summary: &ScoreSummary, x: f32, y: f32, font_size: f32) {
+    let mut lines = vec![
+        summary.rule.clone(),
+        format!("score {:.0}  acc {:.2}%", summary.score, summary.accuracy),
+        format!("combo {}  max {}", summary.combo, summary.max_combo),
+    ];
+    lines.extend(
+        summary
+            .counts
+            .iter()
+            .map(|(judgement, count)| format!("{judgement:?} {count}")),
+    );
+    lines.push(format!("Miss {}", summary.misses));
+    if let Some((dx, max_dx)) = summary.dx_score {
+        lines.push(format!("DX {dx}/{max_dx}"));
+    }
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(line, x, y + i as f32 * font_size, font_size, BLACK);
+    }
 }
 
 #[macroquad::main("Falling Block With Tokio Timer")]
@@ -32,77 +77,113 @@ async fn main() {
     // 创建用于排序渲染事件的堆
     let mut sort_heap = WidgetForDisplayHeap::new();
 
-    let block_size = vec2(100.0, 30.0);
-    let initial_position_1 = vec2(screen_width() / 4.0 - block_size.x, 50.0);
-    let initial_position_2 = vec2(screen_width() / 2.0 - block_size.x, 50.0);
-    let initial_position_3 = vec2(screen_width() * 3.0 / 4.0 - block_size.x, 50.0);
-    let initial_position_4 = vec2(screen_width() - block_size.x, 50.0);
     let ground_y = screen_height() - 100.0;
-    let velocity: f32 = 5000.0;
+    // 每单位卷轴位置（流速为1时即每毫秒）对应的像素数
+    let scroll_speed: f32 = 1.0;
     let mut sliding_window = SlidingWindow::new();
     // let (tx_rt_event, mut rx_rt_event) = mpsc::channel(200);
 
-    let base_time = Utc::now() + chrono::Duration::seconds(5);
-    let (widget_vec, widget_display_vec) = parse_osu_file(
-        env!("CARGO_MANIFEST_DIR").to_string() + "/src/bin/test.txt",
-        base_time,
+    let beatmap = load_beatmap();
+    let key_count = beatmap.key_count();
+    let block_size = vec2((screen_width() / key_count as f32).min(100.0), 30.0);
+    // 每条轨道的横坐标
+    let lane_x = |place: WkrType| match place {
+        WkrType::Lane(lane) => screen_width() * (lane + 1) as f32 / key_count as f32 - block_size.x,
+        _ => 0.0,
+    };
+    // 回放：环境变量 MAIRS_REPLAY 指定回放文件时观看回放，不读取输入
+    let chart_hash = chart_hash(&beatmap);
+    let replay = std::env::var("MAIRS_REPLAY")
+        .ok()
+        .map(|path| Replay::load(&path).expect("回放读取失败"));
+    if let Some(replay) = &replay
+        && replay.chart_hash != chart_hash
+    {
+        eprintln!("回放与谱面不一致，判定结果可能不同");
+    }
+    // 判定配置：回放使用记录的配置；否则优先读取环境变量 MAIRS_JUDGEMENT 指定的文件，再按谱面的 OD 计算
+    let profile = Arc::new(match (&replay, std::env::var("MAIRS_JUDGEMENT")) {
+        (Some(replay), _) => replay.profile.clone(),
+        (None, Ok(path)) => JudgementProfile::load(&path).expect("判定配置读取失败"),
+        (None, Err(_)) => beatmap.judgement_profile(),
+    });
+    let (widget_vec, widget_display_vec) = beatmap.build_widgets(
+        &profile,
         screen_height() as f64 + 1000.,
-        velocity.into(),
+        scroll_speed.into(),
     );
+    let scroll_map = beatmap.scroll_map();
+    // 环境变量 MAIRS_AUTOPLAY=1 时自动游玩，输入由谱面生成
+    let replay = replay.or_else(|| {
+        (std::env::var("MAIRS_AUTOPLAY").as_deref() == Ok("1"))
+            .then(|| autoplay_replay(&widget_vec, chart_hash.clone(), (*profile).clone()))
+    });
+    // 计分规则：环境变量 MAIRS_SCORE 可选 v1/v2/maimai，默认为 ScoreV1
+    let rule = std::env::var("MAIRS_SCORE")
+        .ok()
+        .and_then(|name| rule_by_name(&name))
+        .unwrap_or_else(|| rule_by_name("v1").unwrap());
+    let mut recording = Replay::new(chart_hash, (*profile).clone());
+    recording
+        .settings
+        .insert("score".to_string(), rule.name().to_string());
+    let mut scorer = Scorer::for_widgets(rule, &widget_vec);
     let (rt_event_sndr, mut rt_event_rcvr) = tokio::sync::mpsc::channel(10000);
+    // 误差条的半宽对应最宽的判定范围
+    let error_range_ms = profile.latest().num_milliseconds().max(1) as f32;
+    let mut hit_errors = HitErrors::new(30);
+    // 最近一次 FAST/SLOW 提示及其出现时刻
+    let mut last_timing: Option<(Timing, ChartTime)> = None;
+    // 按键绑定：读取环境变量 MAIRS_KEYBINDINGS 指定的文件，默认为当前目录下的 keybindings.toml
+    let lanes = KeyBindings::load(
+        std::env::var("MAIRS_KEYBINDINGS").unwrap_or_else(|_| "keybindings.toml".to_string()),
+    )
+    .expect("按键配置读取失败")
+    .lanes(key_count);
+    // 读取键盘输入时每条轨道都必须绑定按键
+    let unbound = lanes.unbound();
+    if replay.is_none() && !unbound.is_empty() {
+        panic!("{key_count}键的轨道 {unbound:?} 没有绑定按键，请先运行 bind_keys {key_count}");
+    }
+    // 判定线程的运行时，输入后端需要在其中打开
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    // 观看回放或自动游玩时不打开（也不独占）输入设备，也不保存回放
+    let watching = replay.is_some();
+    let (mut input_backend, key_rx) = match watching {
+        true => None,
+        false => Some(rt.block_on(open_backend(ListenerConfig::from_env()))),
+    }
+    .unzip();
+    if let Some(input_backend) = &input_backend {
+        println!("输入后端: {}", input_backend.name());
+        recording
+            .settings
+            .insert("input".to_string(), input_backend.name().to_string());
+    }
+    // 游玩中的输入事件由判定线程记录
+    let recording = Arc::new(Mutex::new(recording));
+    let recording_input = recording.clone();
+    // 谱面准备完毕后开始游玩，歌曲在5秒后开始
+    // 判定、渲染与过期检查共用的时钟
+    let clock: SharedClock = Arc::new(PlaySession::start_at(-ChartTime::seconds(5)));
+    let clk_stats = Arc::new(ClkStats::default());
+    let (clock_input, clk_stats_input) = (clock.clone(), clk_stats.clone());
+    // 输入线程更新、渲染线程读取的轨道按住状态
+    let held = Arc::new(HeldLanes::new(key_count));
+    let held_input = held.clone();
+    // 内核时间戳到处理按键的延迟
+    let latency = Arc::new(InputLatency::default());
+    let latency_input = latency.clone();
     thread::spawn(move || {
-        let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
             let mut hndl_vec = vec![];
 
-            let wkr1_ppty = (
-                WkrType::Wkr1,
-                WorkerMode::ProcessOnce,
-                BuildBoxedEventSelector(|event_tp: &EventType| match event_tp {
-                    EventType::D => true,
-                    EventType::All => true,
-                    _ => false,
-                }),
-            );
-            let wkr2_ppty = (
-                WkrType::Wkr2,
-                WorkerMode::ProcessOnce,
-                BuildBoxedEventSelector(|event_tp: &EventType| match event_tp {
-                    EventType::F => true,
-                    EventType::All => true,
-                    _ => false,
-                }),
-            );
-            let wkr3_ppty = (
-                WkrType::Wkr3,
-                WorkerMode::ProcessOnce,
-                BuildBoxedEventSelector(|event_tp: &EventType| match event_tp {
-                    EventType::J => true,
-                    EventType::All => true,
-                    _ => false,
-                }),
-            );
-            let wkr4_ppty = (
-                WkrType::Wkr4,
-                WorkerMode::ProcessOnce,
-                BuildBoxedEventSelector(|event_tp: &EventType| match event_tp {
-                    EventType::K => true,
-                    EventType::All => true,
-                    _ => false,
-                }),
-            );
-            let wkr0_ppty = (
-                WkrType::Wkr0,
-                WorkerMode::ProcessMultiTimes,
-                BuildBoxedEventSelector(|event_tp: &EventType| true),
+            let wkr_ppty_vec = worker_properties(
+                std::iter::once(WkrType::Wkr0).chain((0..key_count).map(WkrType::Lane)),
             );
 
-            let (event_sndr, wkr_hndl) = WorkerPool::build(
-                vec![wkr0_ppty, wkr1_ppty, wkr2_ppty, wkr3_ppty, wkr4_ppty],
-                widget_vec,
-                rt_event_sndr.clone(),
-            )
-            .await;
+            let (event_sndr, wkr_hndl) =
+                WorkerPool::build(wkr_ppty_vec, widget_vec, rt_event_sndr.clone()).await;
 
             hndl_vec.push(wkr_hndl.input_worker_handle);
             hndl_vec.push(wkr_hndl.widget_router_handle);
@@ -112,12 +193,37 @@ async fn main() {
 
             hndl_vec.push(tokio::spawn(async move {
                 while let Some(event) = event_mpsc_rcvr.recv().await {
+                    recording_input.lock().unwrap().record(&event);
                     event_sndr.send(event).await;
                 }
             }));
 
-            hndl_vec.push(start_clk(rt_event_sndr, event_mpsc_sndr.clone()).await);
-            hndl_vec.push(start_key_listen(event_mpsc_sndr).await);
+            hndl_vec.push(
+                start_clk(
+                    rt_event_sndr,
+                    event_mpsc_sndr.clone(),
+                    clock_input.clone(),
+                    clk_stats_input,
+                )
+                .await,
+            );
+            hndl_vec.push(match (&replay, key_rx) {
+                (Some(replay), _) => {
+                    start_replay_feed(event_mpsc_sndr, replay, clock_input, held_input).await
+                }
+                (None, None) => unreachable!("不观看回放时已打开输入后端"),
+                (None, Some(key_rx)) => {
+                    start_key_listen(
+                        event_mpsc_sndr,
+                        key_rx,
+                        lanes,
+                        clock_input,
+                        held_input,
+                        latency_input,
+                    )
+                    .await
+                }
+            });
 
             for hndl in hndl_vec {
                 hndl.await.unwrap();
@@ -135,36 +241,69 @@ async fn main() {
 
     loop {
         let return_event = rt_event_rcvr.blocking_recv().unwrap();
+        if let Some(input_backend) = &mut input_backend {
+            input_backend.poll();
+        }
         // println!("{return_event:#?}");
-        let now = Utc::now();
+        let now = clock.now();
         // println!("{now}");
-        sliding_window
-            .end_move_while(|e| e.time_stamp_general - chrono::Duration::seconds(5) < now);
+        // 当前卷轴位置，音符与判定线的距离由卷轴位置之差决定
+        let now_position = scroll_map.position_at(now.as_seconds_f64() * 1000.);
+        sliding_window.end_move_while(|e| e.time_stamp_general - ChartTime::seconds(5) < now);
         sliding_window.start_move_while(|e| e.deleted);
         if sliding_window.is_end() {
             break;
         }
 
+        // 错过的音符不再绘制
+        if let RuntimeEvent::Missed(miss) = &return_event
+            && let Some(its) = sliding_window.as_slice()
+        {
+            its.iter_mut()
+                .filter(|it| it.id == miss.id)
+                .for_each(|it| it.deleted = true);
+        }
+
+        scorer.apply(&return_event);
+        if let RuntimeEvent::Some(rtv) = &return_event
+            && !rtv.is_blank
+        {
+            hit_errors.record(rtv);
+            if let Some(timing) = Timing::of(rtv) {
+                last_timing = Some((timing, now));
+            }
+        }
+
         clear_background(WHITE);
 
         draw_line(0.0, ground_y, screen_width(), ground_y, 3.0, YELLOW);
+        // 按住的轨道在判定线上方高亮
+        for lane in held.held() {
+            draw_rectangle(
+                lane_x(WkrType::Lane(lane)),
+                ground_y - 200.,
+                block_size.x,
+                200.,
+                Color::new(0.5, 0.7, 1.0, 0.3),
+            );
+        }
 
         if let Some(its) = sliding_window.as_slice() {
-            its.iter_mut().for_each(|it| {
-                let initial_position_x = match it.place {
-                    WkrType::Wkr1 => initial_position_1.x,
-                    WkrType::Wkr2 => initial_position_2.x,
-                    WkrType::Wkr3 => initial_position_3.x,
-                    WkrType::Wkr4 => initial_position_4.x,
-                    _ => initial_position_1.x,
-                };
-                draw_rectangle(
-                    initial_position_x,
-                    ground_y - (it.time_hit - now).as_seconds_f32() * 1000.,
-                    block_size.x,
-                    block_size.y,
-                    GRAY,
-                );
+            its.iter_mut().filter(|it| !it.deleted).for_each(|it| {
+                let initial_position_x = lane_x(it.place);
+                // 长条身体，从尾部画到头部
+                let head_y = ground_y - (it.position - now_position) as f32 * scroll_speed;
+                if let Some(position_end) = it.position_end {
+                    let tail_y = ground_y - (position_end - now_position) as f32 * scroll_speed;
+                    draw_rectangle(
+                        initial_position_x + block_size.x * 0.2,
+                        tail_y,
+                        block_size.x * 0.6,
+                        head_y - tail_y,
+                        LIGHTGRAY,
+                    );
+                }
+                draw_rectangle(initial_position_x, head_y, block_size.x, block_size.y, GRAY);
                 if let RuntimeEvent::Some(rtv) = &return_event
                     && !rtv.is_blank
                 {
@@ -172,28 +311,101 @@ async fn main() {
                     if it.id == rtv.id && !rtv.is_blank {
                         draw_rectangle(
                             initial_position_x,
-                            ground_y - (it.time_hit - now).as_seconds_f32() * 1000.,
+                            head_y,
                             block_size.x,
                             block_size.y,
-                            match rtv.judgement {
-                                Judgement::CriticalPerfect => YELLOW,
-                                Judgement::Perfect => PINK,
-                                Judgement::Good => GREEN,
-                            },
+                            judgement_color(rtv.judgement),
                         );
                     } else {
-                        draw_rectangle(
-                            initial_position_x,
-                            ground_y - (it.time_hit - now).as_seconds_f32() * 1000.,
-                            block_size.x,
-                            block_size.y,
-                            GRAY,
-                        )
+                        draw_rectangle(initial_position_x, head_y, block_size.x, block_size.y, GRAY)
                     }
                 }
             });
         }
 
+        // FAST/SLOW 提示保持半秒
+        if let Some((timing, at)) = last_timing
+            && now - at < ChartTime::milliseconds(500)
+        {
+            let (text, color) = match timing {
+                Timing::Fast => ("FAST", BLUE),
+                Timing::Slow => ("SLOW", RED),
+            };
+            draw_text(text, screen_width() / 2. - 30., ground_y - 40., 32., color);
+        }
+
+        // 误差条：中线为准确时刻，左侧为提前，右侧为延后
+        let bar_center = screen_width() / 2.;
+        let bar_half = 150.;
+        let bar_y = ground_y + 40.;
+        draw_line(
+            bar_center - bar_half,
+            bar_y,
+            bar_center + bar_half,
+            bar_y,
+            2.,
+            LIGHTGRAY,
+        );
+        draw_line(bar_center, bar_y - 12., bar_center, bar_y + 12., 2., BLACK);
+        for sample in hit_errors.recent() {
+            let x =
+                bar_center + (sample.offset_ms as f32 / error_range_ms).clamp(-1., 1.) * bar_half;
+            draw_line(
+                x,
+                bar_y - 8.,
+                x,
+                bar_y + 8.,
+                2.,
+                judgement_color(sample.judgement),
+            );
+        }
+        draw_text(
+            &format!(
+                "UR {:.2}  mean {:+.1}ms  input {:.1}/{:.1}ms  missed ticks {}",
+                hit_errors.unstable_rate(),
+                hit_errors.mean(),
+                latency.mean().as_secs_f64() * 1000.,
+                latency.max().as_secs_f64() * 1000.,
+                clk_stats.missed()
+            ),
+            10.,
+            30.,
+            24.,
+            BLACK,
+        );
+        let summary = scorer.summary();
+        draw_text(
+            &format!(
+                "{:.0}  {:.2}%  {}x",
+                summary.score, summary.accuracy, summary.combo
+            ),
+            screen_width() - 320.,
+            30.,
+            24.,
+            BLACK,
+        );
+
+        next_frame().await;
+    }
+
+    // 释放输入设备，独占的键盘解除独占后结算画面才能收到按键
+    drop(input_backend);
+
+    // 结算画面，按 Esc 或回车退出
+    let summary = scorer.summary();
+    println!("{summary:#?}");
+    // 保存本局回放：环境变量 MAIRS_REPLAY_OUT 指定路径，默认为当前目录下的 last_replay.mrp
+    if !watching {
+        let replay_path =
+            std::env::var("MAIRS_REPLAY_OUT").unwrap_or_else(|_| "last_replay.mrp".to_string());
+        match recording.lock().unwrap().save(&replay_path) {
+            Ok(()) => println!("回放已保存到 {replay_path}"),
+            Err(e) => eprintln!("回放保存失败: {e}"),
+        }
+    }
+    while !is_key_pressed(KeyCode::Escape) && !is_key_pressed(KeyCode::Enter) {
+        clear_background(WHITE);
+        draw_summary(&summary, 40., 60., 32.);
         next_frame().await;
     }
 }
@@ -1,18 +1,30 @@
-use chrono::{DateTime, Duration, Utc};
-use rust_mai::{
-    parser::parse_osu_file,
-    types::{Widget, WidgetForDisplay, WkrType},
-};
+use rust_mai::parser::parse_osu_file;
 
 #[tokio::main]
 async fn main() {
-    let (widgets, widgets_for_display) = parse_osu_file(
-        "/home/neuron/Projects/rust/RustMai/rust_mai/src/bin/test.txt",
-        Utc::now(),
-        1200.,
-        20.,
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or(env!("CARGO_MANIFEST_DIR").to_string() + "/src/bin/test.txt");
+    let beatmap = match parse_osu_file(&path) {
+        Ok(beatmap) => beatmap,
+        Err(e) => {
+            eprintln!("{path}: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    println!(
+        "{} - {} [{}] ({} timing points, {} hit objects)",
+        beatmap.metadata.artist,
+        beatmap.metadata.title,
+        beatmap.metadata.version,
+        beatmap.timing_points.len(),
+        beatmap.hit_objects.len()
     );
 
+    let (widgets, widgets_for_display) =
+        beatmap.build_widgets(&beatmap.judgement_profile().into(), 1200., 20.);
+
     println!("解析出的Widgets:");
     for widget in &widgets {
         println!("{:?}", widget);
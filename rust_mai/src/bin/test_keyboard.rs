@@ -1,9 +1,30 @@
 use rust_mai::dev_read::*;
+use rust_mai::held::HeldLanes;
+use rust_mai::keybind::LaneMap;
+use rust_mai::session::PlaySession;
+use rust_mai::types::ChartTime;
+
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() {
     let (tx, mut rx) = tokio::sync::mpsc::channel(100);
-    let hndl = start_key_listen(tx).await;
+    let key_count = std::env::args()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(4);
+    let (_listener, key_rx) = AsyncKeyboardListener::new()
+        .await
+        .expect("无法读取输入设备");
+    let hndl = start_key_listen(
+        tx,
+        key_rx,
+        LaneMap::default_for(key_count),
+        Arc::new(PlaySession::start_at(ChartTime::zero())),
+        Arc::new(HeldLanes::new(key_count)),
+        Arc::new(InputLatency::default()),
+    )
+    .await;
     while let Some(event) = rx.recv().await {
         println!("{event:#?}");
     }
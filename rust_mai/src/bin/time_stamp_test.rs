@@ -1,18 +1,19 @@
-use chrono::Utc;
-use tokio::time::{Duration, sleep};
+use std::time::Instant;
 
 struct TimeS<T: Ord> {
+    #[allow(dead_code)]
     stamp: T,
 }
 
+// 游戏计时使用单调时钟，连续取得的时刻不会回退
 #[tokio::main]
 async fn main() {
     loop {
-        let time_stamp_1 = Utc::now();
-        let time_stamp_2 = Utc::now();
-        let times = TimeS {
+        let time_stamp_1 = Instant::now();
+        let time_stamp_2 = Instant::now();
+        let _times = TimeS {
             stamp: time_stamp_1,
         };
-        assert!(time_stamp_2 > time_stamp_1);
+        assert!(time_stamp_2 >= time_stamp_1);
     }
 }
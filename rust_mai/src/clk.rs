@@ -1,45 +1,108 @@
-use chrono::Utc;
 use general_time_event_driven::types::RuntimeEvent;
-use tokio::{task::JoinHandle, time::Duration};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::time::{Duration, Instant, MissedTickBehavior};
+use tokio::{task::JoinHandle, time::interval};
 
-use crate::types::{Event, RtV};
+use crate::clock::SharedClock;
+use crate::types::{Event, EventType, RtV};
 
 const FPS: f32 = 120.0;
 const EVENT_FRAC: u8 = 10;
 
+// 时钟线程的统计，可在线程间共享
+#[derive(Debug, Default)]
+pub struct ClkStats {
+    ticks: AtomicU64,
+    missed: AtomicU64,
+}
+
+impl ClkStats {
+    pub fn ticks(&self) -> u64 {
+        self.ticks.load(Ordering::Relaxed)
+    }
+
+    // 因线程繁忙而跳过的节拍数
+    pub fn missed(&self) -> u64 {
+        self.missed.load(Ordering::Relaxed)
+    }
+}
+
+// 以 FPS 的固定频率唤醒渲染线程，每 EVENT_FRAC 帧向判定线程发送一次时钟事件，使过期的组件判为 Miss
+//
+// 节拍按固定间隔排列，不随处理耗时累积漂移；错过的节拍直接跳过并计入 `stats`
 pub async fn start_clk(
     sndr_playtrd: tokio::sync::mpsc::Sender<RuntimeEvent<RtV>>,
     sndr_eventtrd: tokio::sync::mpsc::Sender<Event>,
+    clock: SharedClock,
+    stats: Arc<ClkStats>,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
+        let period = Duration::from_secs_f32(1.0 / FPS);
+        let mut ticker = interval(period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
         let mut cnt: u8 = 0;
         loop {
+            let scheduled = ticker.tick().await;
+            let missed = (Instant::now() - scheduled).as_nanos() / period.as_nanos();
+            if missed > 0 {
+                stats.missed.fetch_add(missed as u64, Ordering::Relaxed);
+            }
+            stats.ticks.fetch_add(1, Ordering::Relaxed);
+
             cnt += 1;
             cnt %= EVENT_FRAC;
 
-            match cnt {
-                /*
-                                0 => sndr_eventtrd
-                                    .send(Event {
-                                        time_stamp: Utc::now(),
-                                        event_ppty: crate::types::EventType::All,
-                                    })
-                                    .await
-                                    .unwrap(),
-                */
+            let sent = match cnt {
+                0 => sndr_eventtrd
+                    .send(Event {
+                        time_stamp: clock.now(),
+                        event_ppty: EventType::All,
+                    })
+                    .await
+                    .is_ok(),
                 _ => sndr_playtrd
-                    .send(RuntimeEvent::Some(RtV {
-                        is_blank: true,
-                        id: 0,
-                        judgement: crate::types::Judgement::Good,
-                    }))
+                    .send(RuntimeEvent::Some(RtV::blank(0)))
                     .await
-                    .unwrap(),
+                    .is_ok(),
+            };
+            // 接收端关闭时结束
+            if !sent {
+                break;
             }
+        }
+    })
+}
 
-            // println!("cnt");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::PlaySession;
+    use crate::types::ChartTime;
 
-            tokio::time::sleep(Duration::from_secs_f32(1.0 / FPS)).await;
+    #[tokio::test(start_paused = true)]
+    async fn ticks_at_fixed_rate_and_counts_missed() {
+        let (play_sndr, mut play_rcvr) = tokio::sync::mpsc::channel(1000);
+        let (event_sndr, mut event_rcvr) = tokio::sync::mpsc::channel(1000);
+        let stats = Arc::new(ClkStats::default());
+        let clock: SharedClock = Arc::new(PlaySession::start_at(ChartTime::zero()));
+        let hndl = start_clk(play_sndr, event_sndr, clock, stats.clone()).await;
+
+        // 一秒内共120个节拍，其中12个为时钟事件
+        tokio::time::sleep(Duration::from_millis(995)).await;
+        assert_eq!(stats.ticks(), 120);
+        assert_eq!(stats.missed(), 0);
+        let mut events = 0;
+        while let Ok(event) = event_rcvr.try_recv() {
+            assert_eq!(event.event_ppty, EventType::All);
+            events += 1;
         }
-    })
+        assert_eq!(events, 12);
+        let mut frames = 0;
+        while play_rcvr.try_recv().is_ok() {
+            frames += 1;
+        }
+        assert_eq!(frames, 108);
+        hndl.abort();
+    }
 }
@@ -0,0 +1,249 @@
+use crate::session::PlaySession;
+use crate::types::ChartTime;
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// 游戏时钟，给出当前的谱面时间
+///
+/// 判定、渲染与过期检查读取同一个时钟；输入事件按发生时刻换算，不受处理延迟影响
+pub trait Clock: Send + Sync {
+    /// 当前的谱面时间
+    fn now(&self) -> ChartTime;
+
+    /// 某一单调时钟时刻对应的谱面时间
+    fn chart_time_at(&self, instant: Instant) -> ChartTime {
+        let now = Instant::now();
+        match instant <= now {
+            true => self.now() - since(now, instant),
+            false => self.now() + since(instant, now),
+        }
+    }
+}
+
+/// 线程间共享的时钟
+pub type SharedClock = Arc<dyn Clock>;
+
+fn since(later: Instant, earlier: Instant) -> ChartTime {
+    ChartTime::from_std(later - earlier).expect("游玩时长不会溢出")
+}
+
+impl Clock for PlaySession {
+    fn now(&self) -> ChartTime {
+        PlaySession::now(self)
+    }
+
+    fn chart_time_at(&self, instant: Instant) -> ChartTime {
+        PlaySession::chart_time_at(self, instant)
+    }
+}
+
+/// 手动推进的时钟，用于可重复的测试
+///
+/// 与单调时钟没有对应关系，任何时刻的输入都按当前时间记录
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: Mutex<ChartTime>,
+}
+
+impl ManualClock {
+    pub fn new(start: ChartTime) -> Self {
+        Self {
+            now: Mutex::new(start),
+        }
+    }
+
+    pub fn set(&self, chart_time: ChartTime) {
+        *self.now.lock().unwrap() = chart_time;
+    }
+
+    pub fn advance(&self, duration: ChartTime) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> ChartTime {
+        *self.now.lock().unwrap()
+    }
+
+    fn chart_time_at(&self, _: Instant) -> ChartTime {
+        self.now()
+    }
+}
+
+/// 误差超过此值时直接跳到音频位置（跳转、缓冲不足等）
+const SNAP_THRESHOLD_MS: i64 = 50;
+/// 每次音频回调修正误差的比例
+const SMOOTHING: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy)]
+struct AudioState {
+    // 外推的基准：谱面时间 `position` 对应单调时钟的 `at`
+    position: ChartTime,
+    at: Instant,
+    // 最近一次返回的时间，保证时钟不回退
+    last: ChartTime,
+}
+
+impl AudioState {
+    fn estimate_at(&self, instant: Instant) -> ChartTime {
+        match instant >= self.at {
+            true => self.position + since(instant, self.at),
+            false => self.position - since(self.at, instant),
+        }
+    }
+}
+
+/// 以音频播放位置为准的时钟
+///
+/// 音频回调通过 `update` 报告播放位置，两次回调之间按单调时钟外推。
+/// 小的误差逐步修正，大的误差直接跳转；返回的时间不会回退。
+/// 音频开始播放前（前奏等待）按创建时的 `PlaySession` 计时
+#[derive(Debug)]
+pub struct AudioClock {
+    state: Mutex<AudioState>,
+}
+
+impl AudioClock {
+    pub fn new(session: PlaySession) -> Self {
+        let at = Instant::now();
+        let position = session.chart_time_at(at);
+        Self {
+            state: Mutex::new(AudioState {
+                position,
+                at,
+                last: position,
+            }),
+        }
+    }
+
+    /// 音频回调报告的播放位置
+    pub fn update(&self, position: ChartTime) {
+        self.update_at(position, Instant::now());
+    }
+
+    /// 报告 `at` 时刻的播放位置
+    pub fn update_at(&self, position: ChartTime, at: Instant) {
+        let mut state = self.state.lock().unwrap();
+        let estimate = state.estimate_at(at);
+        let error = position - estimate;
+        state.position = match error.num_milliseconds().abs() > SNAP_THRESHOLD_MS {
+            true => position,
+            false => {
+                let micros = error.num_microseconds().unwrap_or(0) as f64 * SMOOTHING;
+                estimate + ChartTime::microseconds(micros as i64)
+            }
+        };
+        state.at = at;
+        // 跳转到更早的位置时允许回退
+        if position < state.last - ChartTime::milliseconds(SNAP_THRESHOLD_MS) {
+            state.last = state.position;
+        }
+    }
+
+    fn now_at(&self, instant: Instant) -> ChartTime {
+        let mut state = self.state.lock().unwrap();
+        let now = state.estimate_at(instant).max(state.last);
+        state.last = now;
+        now
+    }
+}
+
+impl Clock for AudioClock {
+    fn now(&self) -> ChartTime {
+        self.now_at(Instant::now())
+    }
+
+    fn chart_time_at(&self, instant: Instant) -> ChartTime {
+        self.state.lock().unwrap().estimate_at(instant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dev_read::{InputLatency, KeyEvent, KeyInput, start_key_listen};
+    use crate::held::HeldLanes;
+    use crate::judgement::JudgementProfile;
+    use crate::keybind::LaneMap;
+    use crate::types::*;
+    use general_time_event_driven::types::RuntimeEvent;
+    use general_time_event_driven::worker_pool::WorkerPool;
+    use std::time::Duration;
+
+    #[test]
+    fn audio_clock_smooths_and_never_goes_back() {
+        let base = Instant::now();
+        let at = |ms: u64| base + Duration::from_millis(ms);
+        let clock = AudioClock::new(PlaySession::anchored(base, ChartTime::zero()));
+        clock.update_at(ms(0), base);
+        assert_eq!(clock.now_at(at(100)), ms(100));
+
+        // 音频回调晚了10毫秒，只修正一部分
+        clock.update_at(ms(90), at(100));
+        assert_eq!(clock.now_at(at(100)), ms(100));
+        assert_eq!(clock.chart_time_at(at(200)), ms(199));
+        assert_eq!(clock.now_at(at(200)), ms(199));
+
+        // 大的误差直接跳转
+        clock.update_at(ms(1000), at(200));
+        assert_eq!(clock.now_at(at(200)), ms(1000));
+
+        // 跳回更早的位置
+        clock.update_at(ms(500), at(300));
+        assert_eq!(clock.now_at(at(300)), ms(500));
+    }
+
+    #[tokio::test]
+    async fn scripted_key_press_gives_exact_result() {
+        const KEY_D: u16 = 32;
+        let clock = Arc::new(ManualClock::default());
+        let profile = Arc::new(JudgementProfile::osu_mania(5.));
+        let widgets = vec![
+            Widget::tap_at(0, 1000, WkrType::Lane(0), &profile),
+            Widget::tap_at(1, 2000, WkrType::Lane(0), &profile),
+        ];
+        let (rt_sndr, mut rt_rcvr) = tokio::sync::mpsc::channel(10);
+        let (pool_sndr, _pool) =
+            WorkerPool::build(worker_properties([WkrType::Lane(0)]), widgets, rt_sndr).await;
+        let (key_sndr, key_rcvr) = tokio::sync::mpsc::channel(10);
+        let (event_sndr, mut event_rcvr) = tokio::sync::mpsc::channel(10);
+        start_key_listen(
+            event_sndr,
+            key_rcvr,
+            LaneMap::default_for(4),
+            clock.clone(),
+            Arc::new(HeldLanes::new(4)),
+            Arc::new(InputLatency::default()),
+        )
+        .await;
+
+        // 在 1000ms 与 2030ms 按下 D
+        let mut press = async |at_ms| {
+            clock.set(ChartTime::milliseconds(at_ms));
+            for event in [KeyEvent::Pressed(KEY_D), KeyEvent::Released(KEY_D)] {
+                key_sndr.send(KeyInput::received(event)).await.unwrap();
+                pool_sndr.send(event_rcvr.recv().await.unwrap()).await;
+            }
+            match rt_rcvr.recv().await {
+                Some(RuntimeEvent::Some(rtv)) => rtv,
+                other => panic!("unexpected {other:?}"),
+            }
+        };
+        let expected = |id, judgement, offset, at_ms| RtV {
+            is_blank: false,
+            id,
+            judgement,
+            part: NotePart::Tap,
+            offset: ChartTime::milliseconds(offset),
+            place: WkrType::Lane(0),
+            time_stamp: ChartTime::milliseconds(at_ms),
+        };
+        assert_eq!(
+            press(1000).await,
+            expected(0, Judgement::CriticalPerfect, 0, 1000)
+        );
+        assert_eq!(press(2030).await, expected(1, Judgement::Perfect, 30, 2030));
+    }
+}
@@ -1,11 +1,17 @@
+use crate::clock::SharedClock;
+use crate::held::HeldLanes;
+use crate::keybind::LaneMap;
 use crate::types::*;
-use chrono::Utc;
-use evdev::{Device, EventType};
+use evdev::{AbsoluteAxisCode, AttributeSetRef, Device, EventType, KeyCode};
+use inotify::{Inotify, WatchMask};
+use std::collections::HashSet;
 use std::error::Error;
 use std::fs;
-use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
-use tokio::sync::mpsc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::{mpsc, watch};
 use tokio::task::JoinHandle;
 
 // 定义键盘事件类型
@@ -15,174 +21,499 @@ pub enum KeyEvent {
     Released(u16),
 }
 
+// 系统时钟下的内核时间戳换算为单调时钟下的时刻
+pub fn kernel_instant(kernel_time: SystemTime) -> Instant {
+    let now = Instant::now();
+    let delay = SystemTime::now()
+        .duration_since(kernel_time)
+        .unwrap_or_default();
+    now.checked_sub(delay).unwrap_or(now)
+}
+
+// 带内核时间戳的键盘事件
+#[derive(Debug, Clone)]
+pub struct KeyInput {
+    pub event: KeyEvent,
+    // 内核记录的事件时刻
+    pub kernel_time: SystemTime,
+    // 读取到事件的时刻
+    pub received_at: Instant,
+    // 读取时距内核时刻的延迟
+    pub read_delay: Duration,
+}
+
+impl KeyInput {
+    // 读取设备事件，由系统时钟下的内核时间戳换算出单调时钟下的时刻
+    fn read(event: KeyEvent, kernel_time: SystemTime) -> Self {
+        let read_delay = SystemTime::now()
+            .duration_since(kernel_time)
+            .unwrap_or_default();
+        Self {
+            event,
+            kernel_time,
+            received_at: Instant::now(),
+            read_delay,
+        }
+    }
+
+    // 没有内核时间戳的事件，以收到的时刻为发生时刻
+    pub fn received(event: KeyEvent) -> Self {
+        Self {
+            event,
+            kernel_time: SystemTime::now(),
+            received_at: Instant::now(),
+            read_delay: Duration::ZERO,
+        }
+    }
+
+    // 事件在单调时钟下的发生时刻
+    pub fn instant(&self) -> Instant {
+        self.received_at
+            .checked_sub(self.read_delay)
+            .unwrap_or(self.received_at)
+    }
+}
+
+// 内核时间戳与判定线程处理事件之间的延迟统计，可在线程间共享
+#[derive(Debug, Default)]
+pub struct InputLatency {
+    count: AtomicU64,
+    total_us: AtomicU64,
+    last_us: AtomicU64,
+    max_us: AtomicU64,
+}
+
+impl InputLatency {
+    pub fn record(&self, latency: Duration) {
+        let us = latency.as_micros() as u64;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_us.fetch_add(us, Ordering::Relaxed);
+        self.last_us.store(us, Ordering::Relaxed);
+        self.max_us.fetch_max(us, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn last(&self) -> Duration {
+        Duration::from_micros(self.last_us.load(Ordering::Relaxed))
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max_us.load(Ordering::Relaxed))
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            n => Duration::from_micros(self.total_us.load(Ordering::Relaxed) / n),
+        }
+    }
+}
+
+// 设备筛选条件，未设置的条件不限制
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeviceFilter {
+    // 设备名包含的文字，不区分大小写
+    pub name: Option<String>,
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+}
+
+impl DeviceFilter {
+    pub fn matches(&self, name: &str, vendor: u16, product: u16) -> bool {
+        self.name
+            .as_ref()
+            .is_none_or(|part| name.to_lowercase().contains(&part.to_lowercase()))
+            && self.vendor.is_none_or(|v| v == vendor)
+            && self.product.is_none_or(|p| p == product)
+    }
+
+    fn matches_device(&self, device: &Device) -> bool {
+        let id = device.input_id();
+        self.matches(device.name().unwrap_or(""), id.vendor(), id.product())
+    }
+}
+
+// 键盘监听配置
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ListenerConfig {
+    pub filter: DeviceFilter,
+    // 独占设备（EVIOCGRAB），游戏按键不再传给其他程序
+    pub grab: bool,
+}
+
+impl ListenerConfig {
+    // 从环境变量读取：MAIRS_DEVICE_NAME、MAIRS_DEVICE_VENDOR、MAIRS_DEVICE_PRODUCT（十六进制）与 MAIRS_GRAB=1
+    pub fn from_env() -> Self {
+        let hex = |key| {
+            std::env::var(key)
+                .ok()
+                .and_then(|s| u16::from_str_radix(s.trim_start_matches("0x"), 16).ok())
+        };
+        Self {
+            filter: DeviceFilter {
+                name: std::env::var("MAIRS_DEVICE_NAME").ok(),
+                vendor: hex("MAIRS_DEVICE_VENDOR"),
+                product: hex("MAIRS_DEVICE_PRODUCT"),
+            },
+            grab: std::env::var("MAIRS_GRAB").is_ok_and(|s| s == "1"),
+        }
+    }
+}
+
+// 已打开的设备，避免同一设备被重复监听
+type OpenedDevices = Arc<Mutex<HashSet<PathBuf>>>;
+
+const INPUT_DIR: &str = "/dev/input";
+
 // 异步键盘监听器
+//
+// 启动时打开已有的键盘，之后通过 inotify 监听 /dev/input，接入的键盘会自动加入，
+// 拔出的键盘只结束自己的任务。监听器被释放时关闭所有设备并解除独占
 pub struct AsyncKeyboardListener {
-    _tx: mpsc::Sender<KeyEvent>, // 保持发送端存活
+    _tx: mpsc::Sender<KeyInput>, // 保持发送端存活
+    _release: watch::Sender<()>, // 释放时通知设备任务结束
 }
 
 impl AsyncKeyboardListener {
-    // 创建监听器并返回事件接收通道
-    pub async fn new() -> Result<(Self, mpsc::Receiver<KeyEvent>), Box<dyn Error>> {
-        let (tx, rx) = mpsc::channel(32);
+    // 按环境变量中的配置创建监听器并返回事件接收通道
+    pub async fn new() -> Result<(Self, mpsc::Receiver<KeyInput>), Box<dyn Error>> {
+        Self::with_config(ListenerConfig::from_env()).await
+    }
 
-        // 获取所有键盘设备的事件流
-        let devices = Self::find_keyboard_devices()?;
-        let mut tasks = Vec::new();
+    pub async fn with_config(
+        config: ListenerConfig,
+    ) -> Result<(Self, mpsc::Receiver<KeyInput>), Box<dyn Error>> {
+        let (tx, rx) = mpsc::channel(32);
+        let (release, released) = watch::channel(());
+        let config = Arc::new(config);
+        let opened = OpenedDevices::default();
 
+        let devices = Self::find_keyboard_devices();
+        if devices.is_empty() {
+            println!("未找到键盘设备，等待接入");
+        } else if !devices.iter().any(|path| Device::open(path).is_ok()) {
+            return Err("没有读取输入设备的权限".into());
+        }
         for device_path in devices {
-            println!("找到键盘设备: {device_path}");
+            Self::spawn_device(
+                device_path,
+                tx.clone(),
+                opened.clone(),
+                config.clone(),
+                released.clone(),
+            );
+        }
+        Self::watch(tx.clone(), opened, config, released)?;
+
+        Ok((
+            Self {
+                _tx: tx,
+                _release: release,
+            },
+            rx,
+        ))
+    }
 
-            // 为每个设备创建异步任务
-            let device_tx = tx.clone();
-            tasks.push(tokio::spawn(async move {
-                if let Err(e) = Self::handle_device(&device_path, device_tx).await {
-                    eprintln!("设备 {device_path} 处理错误: {e}");
+    // 监听 /dev/input 中新建或权限改变的设备节点
+    fn watch(
+        tx: mpsc::Sender<KeyInput>,
+        opened: OpenedDevices,
+        config: Arc<ListenerConfig>,
+        released: watch::Receiver<()>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut inotify = Inotify::init()?;
+        // udev 在创建节点后才设置权限，因此同时监听 ATTRIB
+        inotify
+            .watches()
+            .add(INPUT_DIR, WatchMask::CREATE | WatchMask::ATTRIB)?;
+        let runtime = tokio::runtime::Handle::current();
+        std::thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            while !tx.is_closed() && released.has_changed().is_ok() {
+                let events = match inotify.read_events_blocking(&mut buffer) {
+                    Ok(events) => events,
+                    Err(e) => {
+                        eprintln!("设备监听错误: {e}");
+                        break;
+                    }
+                };
+                for event in events {
+                    if let Some(name) = event.name
+                        && name.to_string_lossy().starts_with("event")
+                    {
+                        let _guard = runtime.enter();
+                        Self::spawn_device(
+                            Path::new(INPUT_DIR).join(name),
+                            tx.clone(),
+                            opened.clone(),
+                            config.clone(),
+                            released.clone(),
+                        );
+                    }
                 }
-            }));
-        }
+            }
+        });
+        Ok(())
+    }
 
-        // 保持任务运行
+    // 为符合条件的键盘设备创建任务，设备拔出后任务结束并允许重新接入
+    fn spawn_device(
+        device_path: PathBuf,
+        tx: mpsc::Sender<KeyInput>,
+        opened: OpenedDevices,
+        config: Arc<ListenerConfig>,
+        released: watch::Receiver<()>,
+    ) {
+        // 监听器已释放
+        if released.has_changed().is_err() {
+            return;
+        }
+        if !opened.lock().unwrap().insert(device_path.clone()) {
+            return;
+        }
         tokio::spawn(async move {
-            for i in tasks {
-                i.await.unwrap();
+            match Self::handle_device(&device_path, tx, &config, released).await {
+                Ok(true) => println!("键盘已断开: {}", device_path.display()),
+                Ok(false) => {}
+                Err(e) => eprintln!("设备 {} 处理错误: {e}", device_path.display()),
             }
+            opened.lock().unwrap().remove(&device_path);
         });
-
-        Ok((Self { _tx: tx }, rx))
     }
 
-    // 处理单个设备的事件流
+    // 处理单个设备的事件流，设备拔出时返回 `Ok(true)`；
+    // 设备不是符合条件的键盘、接收端关闭或监听器释放时返回 `Ok(false)`
     async fn handle_device(
-        device_path: &str,
-        tx: mpsc::Sender<KeyEvent>,
-    ) -> Result<(), Box<dyn Error>> {
-        // 尝试打开设备
-        let device = match Device::open(Path::new(device_path)) {
-            Ok(d) => d,
-            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
-                // 尝试提升权限
-                if Self::set_device_permissions(device_path).is_ok() {
-                    Device::open(Path::new(device_path))?
-                } else {
-                    return Err("无法提升设备权限".into());
-                }
+        device_path: &Path,
+        tx: mpsc::Sender<KeyInput>,
+        config: &ListenerConfig,
+        mut released: watch::Receiver<()>,
+    ) -> Result<bool, Box<dyn Error>> {
+        let mut device = Device::open(device_path)?;
+
+        if !device.supported_events().contains(EventType::KEY)
+            || !config.filter.matches_device(&device)
+        {
+            return Ok(false);
+        }
+        // 只独占真正的键盘，鼠标、电源键与触摸屏等设备仍可正常使用
+        if config.grab {
+            match is_plain_keyboard(
+                device.supported_keys(),
+                device.supported_absolute_axes(),
+                device
+                    .supported_relative_axes()
+                    .is_some_and(|rel| rel.iter().next().is_some()),
+            ) {
+                true => device.grab()?,
+                false => println!("不独占非键盘设备: {}", device_path.display()),
             }
-            Err(e) => return Err(e.into()),
-        };
+        }
 
-        println!("监听键盘: {}", device.name().unwrap_or("未知设备"));
+        println!(
+            "监听键盘: {} ({})",
+            device.name().unwrap_or("未知设备"),
+            device_path.display()
+        );
 
         // 创建事件流
         let mut stream = device.into_event_stream()?;
 
-        // 处理事件流
-        while let Ok(event) = stream.next_event().await {
+        // 仍按住的按键
+        let mut pressed = HashSet::new();
+
+        // 处理事件流，设备拔出时读取出错并结束
+        loop {
+            let event = tokio::select! {
+                event = stream.next_event() => match event {
+                    Ok(event) => event,
+                    Err(_) => break,
+                },
+                // 关闭事件流即解除独占
+                _ = released.changed() => return Ok(false),
+            };
             if event.event_type() == EventType::KEY {
                 let code = event.code();
 
                 let key_event = match event.value() {
-                    0 => KeyEvent::Released(code),
-                    1 => KeyEvent::Pressed(code),
+                    0 => {
+                        pressed.remove(&code);
+                        KeyEvent::Released(code)
+                    }
+                    1 => {
+                        pressed.insert(code);
+                        KeyEvent::Pressed(code)
+                    }
                     _ => continue, // 忽略长按事件
                 };
 
-                // 发送事件到通道
-                if tx.send(key_event).await.is_err() {
-                    break; // 接收端已关闭
+                // 附带内核时间戳发送事件到通道
+                if tx
+                    .send(KeyInput::read(key_event, event.timestamp()))
+                    .await
+                    .is_err()
+                {
+                    return Ok(false); // 接收端已关闭
                 }
             }
         }
 
-        Ok(())
-    }
-
-    // 自动查找所有键盘设备
-    fn find_keyboard_devices() -> Result<Vec<String>, Box<dyn Error>> {
-        let mut devices = Vec::new();
-
-        // 尝试常见设备路径
-        for i in 0..10 {
-            let path = format!("/dev/input/event{i}");
-            if Path::new(&path).exists()
-                && let Ok(device) = Device::open(Path::new(&path))
-                && device.supported_events().contains(EventType::KEY)
-            {
-                devices.push(path);
-            }
-        }
-
-        // 尝试通过 /dev/input/by-path/ 查找
-        if let Ok(entries) = fs::read_dir("/dev/input/by-path/") {
-            for entry in entries.filter_map(|e| e.ok()) {
-                if let Ok(path) = entry.path().canonicalize() {
-                    let path_str = path.to_string_lossy().into_owned();
-                    if !devices.contains(&path_str)
-                        && let Ok(device) = Device::open(&path)
-                        && device.supported_events().contains(EventType::KEY)
-                    {
-                        devices.push(path_str);
-                    }
-                }
-            }
-        }
-
-        if devices.is_empty() {
-            Err("未找到键盘设备".into())
-        } else {
-            Ok(devices)
+        // 拔出时仍按住的按键视为松开，避免轨道一直处于按住状态
+        for code in pressed {
+            let _ = tx.send(KeyInput::received(KeyEvent::Released(code))).await;
         }
+        Ok(true)
     }
 
-    // 设置设备权限
-    fn set_device_permissions(path: &str) -> Result<(), Box<dyn Error>> {
-        println!("尝试提升设备权限: {path}");
+    // 列出 /dev/input 下的所有事件设备，是否为键盘在打开时判断
+    fn find_keyboard_devices() -> Vec<PathBuf> {
+        let mut devices: Vec<_> = fs::read_dir(INPUT_DIR)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("event"))
+            .map(|entry| entry.path())
+            .collect();
+        devices.sort();
+        devices
+    }
+}
 
-        // 尝试添加读取权限
-        let mut perms = fs::metadata(path)?.permissions();
-        perms.set_mode(perms.mode() | 0o444); // 添加读取权限
-        fs::set_permissions(path, perms)?;
+// 带有字母键与空格键、且没有多点触控或相对坐标轴的设备才视为键盘
+fn is_plain_keyboard(
+    keys: Option<&AttributeSetRef<KeyCode>>,
+    absolute_axes: Option<&AttributeSetRef<AbsoluteAxisCode>>,
+    has_relative_axes: bool,
+) -> bool {
+    let has_keys = keys.is_some_and(|keys| {
+        [KeyCode::KEY_A, KeyCode::KEY_Z, KeyCode::KEY_SPACE]
+            .into_iter()
+            .all(|key| keys.contains(key))
+    });
+    let is_touch = absolute_axes.is_some_and(|axes| {
+        axes.iter()
+            .any(|axis| axis.0 >= AbsoluteAxisCode::ABS_MT_SLOT.0)
+    });
+    has_keys && !is_touch && !has_relative_axes
+}
 
-        Ok(())
+// 各键数的默认键位（evdev 键码，按轨道顺序），只覆盖 1–10 键；
+// 更多的键数没有通用的布局，返回空列表，需要在按键配置中绑定
+pub fn default_lane_keys(key_count: u8) -> &'static [u16] {
+    const SPACE: u16 = 57;
+    const A: u16 = 30;
+    const S: u16 = 31;
+    const D: u16 = 32;
+    const F: u16 = 33;
+    const J: u16 = 36;
+    const K: u16 = 37;
+    const L: u16 = 38;
+    const SEMICOLON: u16 = 39;
+    const V: u16 = 47;
+    const N: u16 = 49;
+    match key_count {
+        1 => &[SPACE],
+        2 => &[F, J],
+        3 => &[F, SPACE, J],
+        4 => &[D, F, J, K],
+        5 => &[D, F, SPACE, J, K],
+        6 => &[S, D, F, J, K, L],
+        7 => &[S, D, F, SPACE, J, K, L],
+        8 => &[A, S, D, F, J, K, L, SEMICOLON],
+        9 => &[A, S, D, F, SPACE, J, K, L, SEMICOLON],
+        10 => &[A, S, D, F, V, N, J, K, L, SEMICOLON],
+        _ => &[],
     }
 }
 
-pub async fn start_key_listen(sndr: tokio::sync::mpsc::Sender<Event>) -> JoinHandle<()> {
-    let (_listener, mut rx) = AsyncKeyboardListener::new().await.unwrap();
+// 将输入后端的按键换算为轨道事件，以按键发生时刻换算的谱面时间为时间戳，调度与通道的延迟不影响判定；
+// 按下与松开经 `held` 过滤后，每条轨道交替发送。发生时刻到处理事件的延迟记录在 `latency` 中
+pub async fn start_key_listen(
+    sndr: tokio::sync::mpsc::Sender<Event>,
+    mut rx: mpsc::Receiver<KeyInput>,
+    lanes: LaneMap,
+    clock: SharedClock,
+    held: Arc<HeldLanes>,
+    latency: Arc<InputLatency>,
+) -> JoinHandle<()> {
     tokio::spawn(async move {
-        while let Some(key_evnt) = rx.recv().await {
-            if let KeyEvent::Pressed(code) = key_evnt {
-                match code {
-                    32 => sndr
-                        .send(Event {
-                            time_stamp: Utc::now(),
-                            event_ppty: crate::types::EventType::D,
-                        })
-                        .await
-                        .unwrap(),
-                    33 => sndr
-                        .send(Event {
-                            time_stamp: Utc::now(),
-                            event_ppty: crate::types::EventType::F,
-                        })
-                        .await
-                        .unwrap(),
-                    36 => sndr
-                        .send(Event {
-                            time_stamp: Utc::now(),
-                            event_ppty: crate::types::EventType::J,
-                        })
-                        .await
-                        .unwrap(),
-                    37 => sndr
-                        .send(Event {
-                            time_stamp: Utc::now(),
-                            event_ppty: crate::types::EventType::K,
-                        })
-                        .await
-                        .unwrap(),
-                    _ => continue,
+        while let Some(input) = rx.recv().await {
+            let instant = input.instant();
+            latency.record(instant.elapsed());
+            let (code, pressed) = match input.event {
+                KeyEvent::Pressed(code) => (code, true),
+                KeyEvent::Released(code) => (code, false),
+            };
+            if let Some(lane) = lanes.lane_of(code) {
+                let event = Event {
+                    time_stamp: clock.chart_time_at(instant),
+                    event_ppty: if pressed {
+                        crate::types::EventType::Press(lane)
+                    } else {
+                        crate::types::EventType::Release(lane)
+                    },
+                };
+                if held.track(&event) {
+                    sndr.send(event).await.unwrap();
                 }
             }
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_timestamp_precedes_receipt() {
+        let input = KeyInput::read(
+            KeyEvent::Pressed(32),
+            SystemTime::now() - Duration::from_millis(8),
+        );
+        assert!(input.read_delay >= Duration::from_millis(8));
+        assert!(input.received_at - input.instant() >= Duration::from_millis(8));
+
+        let latency = InputLatency::default();
+        latency.record(Duration::from_millis(2));
+        latency.record(Duration::from_millis(4));
+        assert_eq!(latency.mean(), Duration::from_millis(3));
+        assert_eq!(latency.max(), Duration::from_millis(4));
+        assert_eq!(latency.last(), Duration::from_millis(4));
+    }
+
+    #[test]
+    fn device_filter() {
+        let filter = DeviceFilter {
+            name: Some("keyboard".to_string()),
+            vendor: Some(0x046d),
+            product: None,
+        };
+        assert!(filter.matches("Logitech USB Keyboard", 0x046d, 0xc31c));
+        assert!(!filter.matches("Logitech USB Keyboard", 0x1234, 0xc31c));
+        assert!(!filter.matches("Logitech Mouse", 0x046d, 0xc31c));
+        assert!(DeviceFilter::default().matches("", 0, 0));
+    }
+
+    #[test]
+    fn grabs_only_plain_keyboards() {
+        use evdev::AttributeSet;
+        let keyboard: AttributeSet<KeyCode> = [KeyCode::KEY_A, KeyCode::KEY_Z, KeyCode::KEY_SPACE]
+            .into_iter()
+            .collect();
+        let power: AttributeSet<KeyCode> = [KeyCode::KEY_POWER].into_iter().collect();
+        let touch: AttributeSet<AbsoluteAxisCode> =
+            [AbsoluteAxisCode::ABS_X, AbsoluteAxisCode::ABS_MT_POSITION_X]
+                .into_iter()
+                .collect();
+        assert!(is_plain_keyboard(Some(&keyboard), None, false));
+        assert!(!is_plain_keyboard(Some(&power), None, false));
+        assert!(!is_plain_keyboard(None, None, false));
+        // 带键盘按键的鼠标与触摸屏
+        assert!(!is_plain_keyboard(Some(&keyboard), None, true));
+        assert!(!is_plain_keyboard(Some(&keyboard), Some(&touch), false));
+    }
+}
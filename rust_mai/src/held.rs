@@ -0,0 +1,80 @@
+use crate::types::{Event, EventType};
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// 各轨道的按住状态
+///
+/// 每条轨道记录当前按住的按键数，同一轨道绑定多个按键时，
+/// 只有第一个按下与最后一个松开会产生事件。可在输入线程与渲染线程间共享
+#[derive(Debug)]
+pub struct HeldLanes {
+    lanes: Vec<AtomicU8>,
+}
+
+impl HeldLanes {
+    pub fn new(key_count: u8) -> Self {
+        Self {
+            lanes: (0..key_count).map(|_| AtomicU8::new(0)).collect(),
+        }
+    }
+
+    /// 记录按下，轨道由松开变为按住时返回 `true`
+    pub fn press(&self, lane: u8) -> bool {
+        self.lanes
+            .get(lane as usize)
+            .is_some_and(|count| count.fetch_add(1, Ordering::AcqRel) == 0)
+    }
+
+    /// 记录松开，轨道由按住变为松开时返回 `true`
+    pub fn release(&self, lane: u8) -> bool {
+        self.lanes.get(lane as usize).is_some_and(|count| {
+            count
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1))
+                .is_ok_and(|n| n == 1)
+        })
+    }
+
+    /// 按事件更新状态，返回事件是否改变了轨道状态；非按键事件总是返回 `true`
+    pub fn track(&self, event: &Event) -> bool {
+        match event.event_ppty {
+            EventType::Press(lane) => self.press(lane),
+            EventType::Release(lane) => self.release(lane),
+            _ => true,
+        }
+    }
+
+    pub fn is_held(&self, lane: u8) -> bool {
+        self.lanes
+            .get(lane as usize)
+            .is_some_and(|count| count.load(Ordering::Acquire) > 0)
+    }
+
+    /// 当前按住的轨道
+    pub fn held(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..self.lanes.len() as u8).filter(|&lane| self.is_held(lane))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lane_held_while_any_key_down() {
+        let held = HeldLanes::new(4);
+        assert!(held.press(1));
+        // 同一轨道的第二个按键
+        assert!(!held.press(1));
+        assert!(!held.release(1));
+        assert!(held.is_held(1));
+        assert!(held.release(1));
+        assert!(!held.is_held(1));
+        // 多余的松开不会产生事件
+        assert!(!held.release(1));
+        assert!(!held.press(9));
+
+        held.press(0);
+        held.press(3);
+        assert_eq!(held.held().collect::<Vec<_>>(), [0, 3]);
+    }
+}
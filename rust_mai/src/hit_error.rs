@@ -0,0 +1,148 @@
+use crate::types::{ChartTime, Judgement, NotePart, RtV};
+
+use std::collections::VecDeque;
+
+/// 提前或延后
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    Fast,
+    Slow,
+}
+
+impl Timing {
+    /// 判定结果对应的提前/延后提示，空返回值与 CriticalPerfect 不提示
+    pub fn of(rtv: &RtV) -> Option<Self> {
+        if rtv.is_blank || rtv.judgement == Judgement::CriticalPerfect {
+            return None;
+        }
+        match rtv.offset.cmp(&ChartTime::zero()) {
+            std::cmp::Ordering::Less => Some(Timing::Fast),
+            std::cmp::Ordering::Greater => Some(Timing::Slow),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+}
+
+/// 一次击打的时间差
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HitSample {
+    /// 时间差（毫秒），为负表示提前
+    pub offset_ms: f64,
+    pub judgement: Judgement,
+}
+
+/// 时间差统计，用于绘制误差条、计算 UR 与校准偏移
+///
+/// 均值与方差按 Welford 算法累加，误差条只保留最近的 `capacity` 次击打
+#[derive(Debug, Clone)]
+pub struct HitErrors {
+    recent: VecDeque<HitSample>,
+    capacity: usize,
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl HitErrors {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            recent: VecDeque::with_capacity(capacity),
+            capacity,
+            count: 0,
+            mean: 0.,
+            m2: 0.,
+        }
+    }
+
+    /// 记录一个判定结果，只统计按下的时间差，空返回值、长条尾部与星星轨迹被忽略
+    ///
+    /// 长条尾部与星星的时间差可能来自过期或过晚松开，会使 UR 与均值失真
+    pub fn record(&mut self, rtv: &RtV) {
+        if rtv.is_blank
+            || !matches!(
+                rtv.part,
+                NotePart::Tap | NotePart::HoldHead | NotePart::Touch
+            )
+        {
+            return;
+        }
+        let offset_ms = rtv.offset.num_microseconds().unwrap_or(0) as f64 / 1000.;
+        if self.recent.len() == self.capacity {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(HitSample {
+            offset_ms,
+            judgement: rtv.judgement,
+        });
+
+        self.count += 1;
+        let delta = offset_ms - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (offset_ms - self.mean);
+    }
+
+    /// 最近的击打，从旧到新
+    pub fn recent(&self) -> impl Iterator<Item = &HitSample> {
+        self.recent.iter()
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// 平均时间差（毫秒），可作为判定偏移的校准值
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// 时间差的标准差（毫秒）
+    pub fn std_dev(&self) -> f64 {
+        match self.count {
+            0 => 0.,
+            n => (self.m2 / n as f64).sqrt(),
+        }
+    }
+
+    /// Unstable Rate，即标准差的十倍
+    pub fn unstable_rate(&self) -> f64 {
+        self.std_dev() * 10.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::WkrType;
+
+    fn hit(ms: i64, judgement: Judgement) -> RtV {
+        RtV {
+            judgement,
+            offset: ChartTime::milliseconds(ms),
+            place: WkrType::Lane(0),
+            is_blank: false,
+            ..RtV::blank(0)
+        }
+    }
+
+    #[test]
+    fn fast_slow_and_unstable_rate() {
+        assert_eq!(Timing::of(&hit(-30, Judgement::Great)), Some(Timing::Fast));
+        assert_eq!(Timing::of(&hit(25, Judgement::Perfect)), Some(Timing::Slow));
+        assert_eq!(Timing::of(&hit(-5, Judgement::CriticalPerfect)), None);
+
+        let mut errors = HitErrors::new(2);
+        errors.record(&RtV::blank(0));
+        // 过晚松开的长条尾部不计入
+        errors.record(&RtV {
+            part: NotePart::HoldTail,
+            ..hit(400, Judgement::Good)
+        });
+        for ms in [-10, 10, -10, 10] {
+            errors.record(&hit(ms, Judgement::Perfect));
+        }
+        assert_eq!(errors.count(), 4);
+        assert_eq!(errors.mean(), 0.);
+        assert_eq!(errors.unstable_rate(), 100.);
+        assert_eq!(errors.recent().count(), 2);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::dev_read::{AsyncKeyboardListener, KeyEvent, KeyInput, ListenerConfig};
+
+use macroquad::input::{KeyCode, get_keys_pressed, get_keys_released};
+use tokio::sync::mpsc;
+
+/// 键盘输入后端
+///
+/// 后端产生的 `KeyInput` 使用 evdev 键码，由 `start_key_listen` 按键位换算为轨道事件
+pub trait InputBackend: Send {
+    fn name(&self) -> &'static str;
+
+    /// 每帧在渲染线程调用，需要在渲染线程读取按键的后端在此发送事件
+    fn poll(&mut self) {}
+}
+
+/// 直接读取 /dev/input 的后端，使用内核时间戳
+pub struct EvdevBackend {
+    _listener: AsyncKeyboardListener,
+}
+
+impl InputBackend for EvdevBackend {
+    fn name(&self) -> &'static str {
+        "evdev"
+    }
+}
+
+/// 读取窗口按键事件的后端，不需要输入设备的读取权限，时间戳为每帧读取的时刻
+pub struct WindowBackend {
+    tx: mpsc::Sender<KeyInput>,
+}
+
+impl WindowBackend {
+    pub fn new() -> (Self, mpsc::Receiver<KeyInput>) {
+        let (tx, rx) = mpsc::channel(32);
+        (Self { tx }, rx)
+    }
+}
+
+impl InputBackend for WindowBackend {
+    fn name(&self) -> &'static str {
+        "window"
+    }
+
+    fn poll(&mut self) {
+        let released = get_keys_released()
+            .into_iter()
+            .filter_map(evdev_code)
+            .map(KeyEvent::Released);
+        let pressed = get_keys_pressed()
+            .into_iter()
+            .filter_map(evdev_code)
+            .map(KeyEvent::Pressed);
+        for event in released.chain(pressed) {
+            // 接收端处理不及时则丢弃，不阻塞渲染
+            let _ = self.tx.try_send(KeyInput::received(event));
+        }
+    }
+}
+
+/// 选择输入后端
+///
+/// 环境变量 MAIRS_INPUT 可指定 `evdev` 或 `window`；未指定时优先使用 evdev，
+/// 无法读取输入设备时改用窗口按键。需在 tokio 运行时中调用
+pub async fn open_backend(
+    config: ListenerConfig,
+) -> (Box<dyn InputBackend>, mpsc::Receiver<KeyInput>) {
+    let window = || {
+        let (backend, rx) = WindowBackend::new();
+        (Box::new(backend) as Box<dyn InputBackend>, rx)
+    };
+    if std::env::var("MAIRS_INPUT").is_ok_and(|s| s == "window") {
+        return window();
+    }
+    match AsyncKeyboardListener::with_config(config).await {
+        Ok((listener, rx)) => (
+            Box::new(EvdevBackend {
+                _listener: listener,
+            }),
+            rx,
+        ),
+        Err(e) => {
+            eprintln!("无法使用 evdev（{e}），改用窗口按键");
+            window()
+        }
+    }
+}
+
+/// 窗口按键对应的 evdev 键码
+pub fn evdev_code(key: KeyCode) -> Option<u16> {
+    use KeyCode::*;
+    let code = match key {
+        Escape => 1,
+        Key1 => 2,
+        Key2 => 3,
+        Key3 => 4,
+        Key4 => 5,
+        Key5 => 6,
+        Key6 => 7,
+        Key7 => 8,
+        Key8 => 9,
+        Key9 => 10,
+        Key0 => 11,
+        Minus => 12,
+        Equal => 13,
+        Backspace => 14,
+        Tab => 15,
+        Q => 16,
+        W => 17,
+        E => 18,
+        R => 19,
+        T => 20,
+        Y => 21,
+        U => 22,
+        I => 23,
+        O => 24,
+        P => 25,
+        LeftBracket => 26,
+        RightBracket => 27,
+        Enter => 28,
+        LeftControl => 29,
+        A => 30,
+        S => 31,
+        D => 32,
+        F => 33,
+        G => 34,
+        H => 35,
+        J => 36,
+        K => 37,
+        L => 38,
+        Semicolon => 39,
+        Apostrophe => 40,
+        GraveAccent => 41,
+        LeftShift => 42,
+        Backslash => 43,
+        Z => 44,
+        X => 45,
+        C => 46,
+        V => 47,
+        B => 48,
+        N => 49,
+        M => 50,
+        Comma => 51,
+        Period => 52,
+        Slash => 53,
+        RightShift => 54,
+        LeftAlt => 56,
+        Space => 57,
+        CapsLock => 58,
+        Up => 103,
+        Left => 105,
+        Right => 106,
+        Down => 108,
+        _ => return None,
+    };
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dev_read::default_lane_keys;
+
+    #[test]
+    fn window_keys_match_default_bindings() {
+        let keys = [
+            KeyCode::A,
+            KeyCode::S,
+            KeyCode::D,
+            KeyCode::F,
+            KeyCode::Space,
+        ];
+        let codes: Vec<_> = keys.into_iter().filter_map(evdev_code).collect();
+        assert_eq!(codes, [30, 31, 32, 33, 57]);
+        let lanes: Vec<_> = [KeyCode::D, KeyCode::F, KeyCode::J, KeyCode::K]
+            .into_iter()
+            .filter_map(evdev_code)
+            .collect();
+        assert_eq!(lanes, default_lane_keys(4));
+        assert_eq!(evdev_code(KeyCode::F12), None);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::types::{ChartTime, Judgement};
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// 一个判定等级的时间范围（毫秒）
+///
+/// `early` 为最多可提前的时间，`late` 为最多可延后的时间，均为非负数
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct JudgementWindow {
+    pub judgement: Judgement,
+    pub early: f64,
+    pub late: f64,
+}
+
+impl JudgementWindow {
+    // 前后对称的范围
+    fn symmetric(judgement: Judgement, ms: f64) -> Self {
+        Self {
+            judgement,
+            early: ms,
+            late: ms,
+        }
+    }
+
+    fn contains(&self, delta_ms: f64) -> bool {
+        (-self.early..=self.late).contains(&delta_ms)
+    }
+}
+
+/// 判定配置
+///
+/// `windows` 按从严到宽排列，判定时取第一个包含时间差的等级
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JudgementProfile {
+    pub name: String,
+    /// 判定偏移（毫秒），为正表示判定时刻整体延后
+    #[serde(default)]
+    pub offset: f64,
+    pub windows: Vec<JudgementWindow>,
+}
+
+/// 判定配置读取错误
+#[derive(Debug)]
+pub enum ProfileError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    /// 没有任何判定范围
+    Empty,
+    /// 判定范围未按从严到宽排列，或含有负数
+    Unordered(Judgement),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::Io(e) => write!(f, "读取失败: {e}"),
+            ProfileError::Toml(e) => write!(f, "判定配置格式错误: {e}"),
+            ProfileError::Empty => write!(f, "判定配置中没有判定范围"),
+            ProfileError::Unordered(judgement) => {
+                write!(f, "判定 {judgement:?} 的范围应不小于前一个等级且非负")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+impl JudgementProfile {
+    /// osu!mania 的判定，由谱面的 OverallDifficulty 决定
+    ///
+    /// MAX/300/200/100/50 依次对应 CriticalPerfect/Perfect/Great/Good/Meh
+    pub fn osu_mania(overall_difficulty: f64) -> Self {
+        let od = overall_difficulty.clamp(0., 10.);
+        Self {
+            name: format!("osu!mania OD{od}"),
+            offset: 0.,
+            windows: vec![
+                JudgementWindow::symmetric(Judgement::CriticalPerfect, 16.),
+                JudgementWindow::symmetric(Judgement::Perfect, 64. - 3. * od),
+                JudgementWindow::symmetric(Judgement::Great, 97. - 3. * od),
+                JudgementWindow::symmetric(Judgement::Good, 127. - 3. * od),
+                JudgementWindow::symmetric(Judgement::Meh, 151. - 3. * od),
+            ],
+        }
+    }
+
+    /// maimai DX 的判定，以60帧每秒的帧数计
+    pub fn maimai_dx() -> Self {
+        let frames = |n: f64| n * 1000. / 60.;
+        Self {
+            name: "maimai DX".to_string(),
+            offset: 0.,
+            windows: vec![
+                JudgementWindow::symmetric(Judgement::CriticalPerfect, frames(1.)),
+                JudgementWindow::symmetric(Judgement::Perfect, frames(2.)),
+                JudgementWindow::symmetric(Judgement::Great, frames(6.)),
+                JudgementWindow::symmetric(Judgement::Good, frames(9.)),
+            ],
+        }
+    }
+
+    /// 从 TOML 文本读取判定配置
+    pub fn from_toml(text: &str) -> Result<Self, ProfileError> {
+        let profile: Self = toml::from_str(text).map_err(ProfileError::Toml)?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// 从 TOML 文件读取判定配置
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ProfileError> {
+        Self::from_toml(&fs::read_to_string(path).map_err(ProfileError::Io)?)
+    }
+
+    /// 检查判定范围是否非空且按从严到宽排列
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        let mut last = (0., 0.);
+        for window in &self.windows {
+            if window.early < last.0 || window.late < last.1 {
+                return Err(ProfileError::Unordered(window.judgement));
+            }
+            last = (window.early, window.late);
+        }
+        match self.windows.is_empty() {
+            true => Err(ProfileError::Empty),
+            false => Ok(()),
+        }
+    }
+
+    /// 判定时间差（`事件时刻 - 音符时刻`）对应的等级，超出所有范围时返回 `None`
+    pub fn grade(&self, delta: ChartTime) -> Option<Judgement> {
+        // 由微秒换算，避免边界上的浮点误差
+        let micros = delta.num_microseconds().unwrap_or(i64::MAX);
+        let delta_ms = micros as f64 / 1000. - self.offset;
+        self.windows
+            .iter()
+            .find(|window| window.contains(delta_ms))
+            .map(|window| window.judgement)
+    }
+
+    /// 最宽范围内最低的等级
+    pub fn lowest(&self) -> Judgement {
+        self.windows.last().map_or(Judgement::Good, |w| w.judgement)
+    }
+
+    /// 音符时刻前多久开始接受判定
+    pub fn earliest(&self) -> ChartTime {
+        self.bound(|window| window.early - self.offset)
+    }
+
+    /// 音符时刻后多久仍接受判定，超过即为 Miss
+    pub fn latest(&self) -> ChartTime {
+        self.bound(|window| window.late + self.offset)
+    }
+
+    fn bound(&self, f: impl Fn(&JudgementWindow) -> f64) -> ChartTime {
+        let ms = self.windows.iter().map(f).fold(0., f64::max);
+        ChartTime::microseconds((ms * 1000.) as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn osu_mania_windows() {
+        let profile = JudgementProfile::osu_mania(8.);
+        let grade = |ms| profile.grade(ChartTime::milliseconds(ms));
+        assert_eq!(grade(-16), Some(Judgement::CriticalPerfect));
+        assert_eq!(grade(40), Some(Judgement::Perfect));
+        assert_eq!(grade(-73), Some(Judgement::Great));
+        assert_eq!(grade(127), Some(Judgement::Meh));
+        assert_eq!(grade(128), None);
+        assert_eq!(profile.latest(), ChartTime::milliseconds(127));
+    }
+
+    #[test]
+    fn load_profile_from_toml() {
+        let profile = JudgementProfile::from_toml(
+            r#"
+            name = "custom"
+            offset = 10
+            windows = [
+                { judgement = "Perfect", early = 20, late = 30 },
+                { judgement = "Good", early = 50, late = 100 },
+            ]
+            "#,
+        )
+        .unwrap();
+        let grade = |ms| profile.grade(ChartTime::milliseconds(ms));
+        assert_eq!(grade(-10), Some(Judgement::Perfect));
+        assert_eq!(grade(40), Some(Judgement::Perfect));
+        assert_eq!(grade(-40), Some(Judgement::Good));
+        assert_eq!(grade(-41), None);
+        assert_eq!(profile.earliest(), ChartTime::milliseconds(40));
+
+        let unordered = JudgementProfile::from_toml(
+            r#"
+            name = "bad"
+            windows = [
+                { judgement = "Perfect", early = 50, late = 50 },
+                { judgement = "Good", early = 20, late = 100 },
+            ]
+            "#,
+        );
+        assert!(matches!(
+            unordered,
+            Err(ProfileError::Unordered(Judgement::Good))
+        ));
+    }
+}
@@ -0,0 +1,318 @@
+use crate::dev_read::{KeyEvent, KeyInput, default_lane_keys};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use tokio::sync::mpsc;
+
+/// 回车键，交互绑定时结束当前轨道
+pub const KEY_ENTER: u16 = 28;
+/// Esc 键，交互绑定时保留当前轨道原有的按键
+pub const KEY_ESC: u16 = 1;
+
+/// 一种键数下各轨道的按键（evdev 键码），每条轨道可绑定多个按键
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LaneMap {
+    pub lanes: Vec<Vec<u16>>,
+}
+
+impl LaneMap {
+    /// 内置的默认键位，每条轨道一个按键；没有默认键位的键数各轨道均未绑定
+    pub fn default_for(key_count: u8) -> Self {
+        let keys = default_lane_keys(key_count);
+        Self {
+            lanes: (0..key_count as usize)
+                .map(|lane| keys.get(lane).map(|&key| vec![key]).unwrap_or_default())
+                .collect(),
+        }
+    }
+
+    pub fn key_count(&self) -> u8 {
+        self.lanes.len() as u8
+    }
+
+    /// 没有绑定任何按键的轨道
+    pub fn unbound(&self) -> Vec<u8> {
+        (0..self.key_count())
+            .filter(|&lane| self.lanes[lane as usize].is_empty())
+            .collect()
+    }
+
+    /// 键码所在的轨道
+    pub fn lane_of(&self, code: u16) -> Option<u8> {
+        self.lanes
+            .iter()
+            .position(|keys| keys.contains(&code))
+            .map(|lane| lane as u8)
+    }
+
+    // 同一按键不能绑定到多条轨道
+    fn validate(&self) -> Result<(), KeyBindError> {
+        let mut seen = Vec::new();
+        for &code in self.lanes.iter().flatten() {
+            if seen.contains(&code) {
+                return Err(KeyBindError::Conflict(code));
+            }
+            seen.push(code);
+        }
+        Ok(())
+    }
+}
+
+/// 按键绑定配置，以键数为键
+///
+/// ```toml
+/// [layouts]
+/// 4 = [[32], [33], [36], [37]]
+/// 7 = [[31], [32], [33], [57, 47], [36], [37], [38]]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyBindings {
+    #[serde(default)]
+    layouts: BTreeMap<String, Vec<Vec<u16>>>,
+}
+
+/// 按键绑定读写错误
+#[derive(Debug)]
+pub enum KeyBindError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Serialize(toml::ser::Error),
+    /// 键数不是数字
+    InvalidKeyCount(String),
+    /// 配置中的轨道数与键数不符
+    LaneCount {
+        key_count: u8,
+        lanes: usize,
+    },
+    /// 同一键码被绑定到多条轨道
+    Conflict(u16),
+}
+
+impl fmt::Display for KeyBindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyBindError::Io(e) => write!(f, "读写失败: {e}"),
+            KeyBindError::Toml(e) => write!(f, "按键配置格式错误: {e}"),
+            KeyBindError::Serialize(e) => write!(f, "按键配置写出失败: {e}"),
+            KeyBindError::InvalidKeyCount(key) => write!(f, "无效的键数: {key}"),
+            KeyBindError::LaneCount { key_count, lanes } => {
+                write!(f, "{key_count}键的配置有 {lanes} 条轨道")
+            }
+            KeyBindError::Conflict(code) => write!(f, "键码 {code} 被绑定到多条轨道"),
+        }
+    }
+}
+
+impl std::error::Error for KeyBindError {}
+
+impl KeyBindings {
+    /// 从 TOML 文本读取
+    pub fn from_toml(text: &str) -> Result<Self, KeyBindError> {
+        let bindings: Self = toml::from_str(text).map_err(KeyBindError::Toml)?;
+        bindings.validate()?;
+        Ok(bindings)
+    }
+
+    pub fn to_toml(&self) -> Result<String, KeyBindError> {
+        toml::to_string(self).map_err(KeyBindError::Serialize)
+    }
+
+    /// 从文件读取，文件不存在时返回空配置（即全部使用默认键位）
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, KeyBindError> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::from_toml(&text),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(KeyBindError::Io(e)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), KeyBindError> {
+        fs::write(path, self.to_toml()?).map_err(KeyBindError::Io)
+    }
+
+    /// 检查键数与轨道数一致且没有重复绑定的按键
+    pub fn validate(&self) -> Result<(), KeyBindError> {
+        for (key, lanes) in &self.layouts {
+            let key_count: u8 = key
+                .parse()
+                .map_err(|_| KeyBindError::InvalidKeyCount(key.clone()))?;
+            if key_count as usize != lanes.len() {
+                return Err(KeyBindError::LaneCount {
+                    key_count,
+                    lanes: lanes.len(),
+                });
+            }
+            LaneMap {
+                lanes: lanes.clone(),
+            }
+            .validate()?;
+        }
+        Ok(())
+    }
+
+    /// 某一键数的键位，未配置时使用默认键位
+    pub fn lanes(&self, key_count: u8) -> LaneMap {
+        match self.layouts.get(&key_count.to_string()) {
+            Some(lanes) => LaneMap {
+                lanes: lanes.clone(),
+            },
+            None => LaneMap::default_for(key_count),
+        }
+    }
+
+    /// 设置某一键数的键位
+    pub fn set(&mut self, lanes: LaneMap) -> Result<(), KeyBindError> {
+        lanes.validate()?;
+        self.layouts
+            .insert(lanes.key_count().to_string(), lanes.lanes);
+        Ok(())
+    }
+}
+
+/// 交互式绑定：依次为每条轨道读取按键
+///
+/// 每条轨道可连续按下多个按键，回车结束该轨道；未按任何按键就回车或按 Esc 时保留 `current` 中的原有按键，
+/// 其中已被前面轨道占用的按键不再保留。原本未绑定或原有按键全部被占用的轨道必须按下至少一个按键。
+/// 已绑定到前面轨道的按键会被忽略。`prompt` 在开始读取每条轨道前调用
+pub async fn bind_interactively(
+    rx: &mut mpsc::Receiver<KeyInput>,
+    current: &LaneMap,
+    mut prompt: impl FnMut(u8),
+) -> LaneMap {
+    let mut lanes: Vec<Vec<u16>> = Vec::with_capacity(current.lanes.len());
+    for (lane, old) in current.lanes.iter().enumerate() {
+        prompt(lane as u8);
+        let kept: Vec<u16> = old
+            .iter()
+            .copied()
+            .filter(|code| !lanes.iter().flatten().any(|k| k == code))
+            .collect();
+        let mut keys = Vec::new();
+        while let Some(input) = rx.recv().await {
+            let KeyEvent::Pressed(code) = input.event else {
+                continue;
+            };
+            match code {
+                KEY_ESC | KEY_ENTER if keys.is_empty() && kept.is_empty() => {}
+                KEY_ESC => {
+                    keys.clear();
+                    break;
+                }
+                KEY_ENTER => break,
+                code if keys.contains(&code) || lanes.iter().flatten().any(|&k| k == code) => {}
+                code => keys.push(code),
+            }
+        }
+        lanes.push(match keys.is_empty() {
+            true => kept,
+            false => keys,
+        });
+    }
+    LaneMap { lanes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant, SystemTime};
+
+    #[test]
+    fn several_keys_per_lane() {
+        let bindings = KeyBindings::from_toml(
+            r#"
+            [layouts]
+            2 = [[33, 34], [36]]
+            "#,
+        )
+        .unwrap();
+        let lanes = bindings.lanes(2);
+        assert_eq!(lanes.lane_of(34), Some(0));
+        assert_eq!(lanes.lane_of(36), Some(1));
+        assert_eq!(lanes.lane_of(32), None);
+        // 未配置的键数使用默认键位
+        assert_eq!(bindings.lanes(4), LaneMap::default_for(4));
+        assert!(bindings.lanes(4).unbound().is_empty());
+        // 没有默认键位的键数得到未绑定的轨道
+        let lanes = bindings.lanes(12);
+        assert_eq!(lanes.key_count(), 12);
+        assert_eq!(lanes.unbound(), (0..12).collect::<Vec<_>>());
+        assert_eq!(
+            KeyBindings::from_toml(&bindings.to_toml().unwrap()).unwrap(),
+            bindings
+        );
+
+        assert!(matches!(
+            KeyBindings::from_toml("[layouts]\n2 = [[33], [33]]"),
+            Err(KeyBindError::Conflict(33))
+        ));
+        assert!(matches!(
+            KeyBindings::from_toml("[layouts]\n3 = [[33]]"),
+            Err(KeyBindError::LaneCount { .. })
+        ));
+    }
+
+    // 依次按下的按键
+    async fn key_inputs(events: &[KeyEvent]) -> mpsc::Receiver<KeyInput> {
+        let (tx, rx) = mpsc::channel(events.len());
+        for event in events {
+            tx.send(KeyInput {
+                event: event.clone(),
+                kernel_time: SystemTime::now(),
+                received_at: Instant::now(),
+                read_delay: Duration::ZERO,
+            })
+            .await
+            .unwrap();
+        }
+        rx
+    }
+
+    #[tokio::test]
+    async fn interactive_binding() {
+        let mut rx = key_inputs(&[
+            KeyEvent::Pressed(30),
+            KeyEvent::Released(30),
+            KeyEvent::Pressed(31),
+            KeyEvent::Pressed(KEY_ENTER),
+            // 已绑定到轨道0的按键被忽略
+            KeyEvent::Pressed(30),
+            KeyEvent::Pressed(KEY_ESC),
+            KeyEvent::Pressed(38),
+            KeyEvent::Pressed(KEY_ENTER),
+            // 未绑定的轨道不能跳过
+            KeyEvent::Pressed(KEY_ENTER),
+            KeyEvent::Pressed(KEY_ESC),
+            KeyEvent::Pressed(44),
+            KeyEvent::Pressed(KEY_ENTER),
+        ])
+        .await;
+        let mut prompted = vec![];
+        let mut current = LaneMap::default_for(3);
+        current.lanes.push(vec![]);
+        let lanes = bind_interactively(&mut rx, &current, |lane| prompted.push(lane)).await;
+        assert_eq!(prompted, [0, 1, 2, 3]);
+        assert_eq!(lanes.lanes, [vec![30, 31], vec![57], vec![38], vec![44]]);
+    }
+
+    #[tokio::test]
+    async fn kept_keys_skip_rebound_ones() {
+        // 4 键默认为 D F J K，将 J 绑定到轨道0
+        let mut rx = key_inputs(&[
+            KeyEvent::Pressed(36),
+            KeyEvent::Pressed(KEY_ENTER),
+            KeyEvent::Pressed(KEY_ENTER),
+            // 轨道2原有的 J 已被占用，不能保留
+            KeyEvent::Pressed(KEY_ENTER),
+            KeyEvent::Pressed(38),
+            KeyEvent::Pressed(KEY_ENTER),
+            KeyEvent::Pressed(KEY_ENTER),
+        ])
+        .await;
+        let lanes = bind_interactively(&mut rx, &LaneMap::default_for(4), |_| {}).await;
+        assert_eq!(lanes.lanes, [vec![36], vec![33], vec![38], vec![37]]);
+        assert!(KeyBindings::default().set(lanes).is_ok());
+    }
+}
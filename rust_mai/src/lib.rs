@@ -1,11 +1,28 @@
-#![feature(allocator_api)]
-
+pub mod autoplay;
+pub mod beatmap;
 pub mod clk;
+pub mod clock;
 pub mod dev_read;
+pub mod held;
+pub mod hit_error;
+pub mod input;
+pub mod judgement;
+pub mod keybind;
+pub mod native;
+pub mod osr;
+pub mod osz;
 pub mod parser;
+pub mod replay;
+pub mod score;
+pub mod scroll;
+pub mod sensor;
+pub mod session;
+pub mod simai;
 pub mod sliding_window;
+pub mod touch;
 pub mod types;
 pub mod widget_for_display_queue;
+pub mod writer;
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
@@ -0,0 +1,184 @@
+use crate::beatmap::Beatmap;
+use crate::simai::Maidata;
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// 当前的原生格式版本
+pub const NATIVE_VERSION: u32 = 1;
+
+// 二进制格式的文件头
+const BINARY_MAGIC: &[u8; 4] = b"MAIR";
+
+/// 原生格式读写错误
+#[derive(Debug)]
+pub enum NativeError {
+    Json(serde_json::Error),
+    TomlSerialize(toml::ser::Error),
+    TomlDeserialize(toml::de::Error),
+    Binary(postcard::Error),
+    /// 二进制数据缺少文件头
+    InvalidMagic,
+    /// 文件版本高于当前支持的版本
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for NativeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NativeError::Json(e) => write!(f, "JSON格式错误: {e}"),
+            NativeError::TomlSerialize(e) => write!(f, "TOML写入失败: {e}"),
+            NativeError::TomlDeserialize(e) => write!(f, "TOML格式错误: {e}"),
+            NativeError::Binary(e) => write!(f, "二进制格式错误: {e}"),
+            NativeError::InvalidMagic => write!(f, "不是MaiRs谱面文件"),
+            NativeError::UnsupportedVersion(version) => {
+                write!(f, "不支持的谱面版本 {version}，当前版本为 {NATIVE_VERSION}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for NativeError {}
+
+/// 谱面内容
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Chart {
+    Osu(Box<Beatmap>),
+    Simai(Maidata),
+}
+
+/// MaiRs 原生谱面格式
+///
+/// 文本形式为 JSON 或 TOML，二进制形式为文件头、版本号与 postcard 编码的谱面
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NativeChart {
+    pub version: u32,
+    pub chart: Chart,
+}
+
+// 只读取版本号，用于在解析谱面内容前检查版本
+#[derive(Deserialize)]
+struct VersionProbe {
+    version: u32,
+}
+
+fn check_version(version: u32) -> Result<(), NativeError> {
+    match version {
+        1..=NATIVE_VERSION => Ok(()),
+        _ => Err(NativeError::UnsupportedVersion(version)),
+    }
+}
+
+impl NativeChart {
+    /// 以当前版本包装谱面
+    pub fn new(chart: Chart) -> Self {
+        Self {
+            version: NATIVE_VERSION,
+            chart,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, NativeError> {
+        serde_json::to_string_pretty(self).map_err(NativeError::Json)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, NativeError> {
+        let probe: VersionProbe = serde_json::from_str(text).map_err(NativeError::Json)?;
+        check_version(probe.version)?;
+        serde_json::from_str(text).map_err(NativeError::Json)
+    }
+
+    pub fn to_toml(&self) -> Result<String, NativeError> {
+        toml::to_string(self).map_err(NativeError::TomlSerialize)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, NativeError> {
+        let probe: VersionProbe = toml::from_str(text).map_err(NativeError::TomlDeserialize)?;
+        check_version(probe.version)?;
+        toml::from_str(text).map_err(NativeError::TomlDeserialize)
+    }
+
+    /// 紧凑的二进制形式
+    pub fn to_binary(&self) -> Result<Vec<u8>, NativeError> {
+        let mut data = BINARY_MAGIC.to_vec();
+        data.extend_from_slice(&self.version.to_le_bytes());
+        data.extend(postcard::to_stdvec(&self.chart).map_err(NativeError::Binary)?);
+        Ok(data)
+    }
+
+    pub fn from_binary(data: &[u8]) -> Result<Self, NativeError> {
+        let body = data
+            .strip_prefix(BINARY_MAGIC)
+            .ok_or(NativeError::InvalidMagic)?;
+        let (version, body) = body
+            .split_first_chunk::<4>()
+            .ok_or(NativeError::InvalidMagic)?;
+        let version = u32::from_le_bytes(*version);
+        check_version(version)?;
+        Ok(Self {
+            version,
+            chart: postcard::from_bytes(body).map_err(NativeError::Binary)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_osu_str;
+    use crate::simai::parse_maidata_str;
+
+    #[test]
+    fn native_round_trip() {
+        let beatmap = parse_osu_str(include_str!("bin/test.txt")).unwrap();
+        let native = NativeChart::new(Chart::Osu(Box::new(beatmap)));
+        assert_eq!(
+            NativeChart::from_json(&native.to_json().unwrap()).unwrap(),
+            native
+        );
+        assert_eq!(
+            NativeChart::from_toml(&native.to_toml().unwrap()).unwrap(),
+            native
+        );
+        assert_eq!(
+            NativeChart::from_binary(&native.to_binary().unwrap()).unwrap(),
+            native
+        );
+
+        let maidata =
+            parse_maidata_str("&wholebpm=120\n&inote_3=1,2h[4:1],1-5[8:1],C1f,\n").unwrap();
+        let native = NativeChart::new(Chart::Simai(maidata));
+        assert_eq!(
+            NativeChart::from_json(&native.to_json().unwrap()).unwrap(),
+            native
+        );
+        assert_eq!(
+            NativeChart::from_toml(&native.to_toml().unwrap()).unwrap(),
+            native
+        );
+        assert_eq!(
+            NativeChart::from_binary(&native.to_binary().unwrap()).unwrap(),
+            native
+        );
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let native = NativeChart {
+            version: NATIVE_VERSION + 1,
+            chart: Chart::Simai(Maidata::default()),
+        };
+        assert!(matches!(
+            NativeChart::from_json(&native.to_json().unwrap()),
+            Err(NativeError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            NativeChart::from_binary(&native.to_binary().unwrap()),
+            Err(NativeError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            NativeChart::from_binary(b"osu file format v14"),
+            Err(NativeError::InvalidMagic)
+        ));
+    }
+}
@@ -0,0 +1,561 @@
+use crate::judgement::JudgementProfile;
+use crate::replay::Replay;
+use crate::types::{ChartTime, Event, EventType, Judgement};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// osu!mania 的模式编号
+pub const MODE_MANIA: u8 = 3;
+
+// 最后一帧以此作为时间差时记录的是随机数种子
+const SEED_FRAME: i64 = -12345;
+// TargetPractice，带此 Mod 的回放末尾多一个浮点数
+const MOD_TARGET_PRACTICE: u32 = 1 << 23;
+
+/// .osr 读写错误
+#[derive(Debug)]
+pub enum OsrError {
+    Io(std::io::Error),
+    Lzma(lzma_rs::error::Error),
+    /// 数据在读完前结束
+    Truncated,
+    /// 字符串标记不是 0x00 或 0x0b
+    InvalidString(u8),
+    /// 字符串长度的编码超过 64 位
+    InvalidLength,
+    /// 回放帧格式错误
+    InvalidFrame(String),
+    /// 不是 osu!mania 的回放
+    NotMania(u8),
+}
+
+impl fmt::Display for OsrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OsrError::Io(e) => write!(f, "读写失败: {e}"),
+            OsrError::Lzma(e) => write!(f, "LZMA数据错误: {e:?}"),
+            OsrError::Truncated => write!(f, "回放数据不完整"),
+            OsrError::InvalidString(mark) => write!(f, "无效的字符串标记 {mark:#04x}"),
+            OsrError::InvalidLength => write!(f, "无效的字符串长度"),
+            OsrError::InvalidFrame(frame) => write!(f, "无效的回放帧: {frame}"),
+            OsrError::NotMania(mode) => write!(f, "模式 {mode} 不是 osu!mania"),
+        }
+    }
+}
+
+impl std::error::Error for OsrError {}
+
+/// 一帧输入，`w|x|y|z`
+///
+/// osu!mania 中 `x` 为按住轨道的位掩码，第 i 位对应第 i 条轨道
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OsrFrame {
+    /// 与上一帧的时间差（毫秒）
+    pub delta_ms: i64,
+    pub x: f32,
+    pub y: f32,
+    pub keys: u32,
+}
+
+impl OsrFrame {
+    /// osu!mania 的按键位掩码
+    pub fn mania_keys(&self) -> u32 {
+        self.x as u32
+    }
+}
+
+/// osu! 的 .osr 回放
+///
+/// `timestamp` 为 Windows ticks（0001 年起的 100 纳秒数），`seed` 为最后一帧记录的随机数种子
+#[derive(Debug, Clone, PartialEq)]
+pub struct Osr {
+    pub mode: u8,
+    pub version: u32,
+    pub beatmap_md5: String,
+    pub player: String,
+    pub replay_md5: String,
+    pub count_300: u16,
+    pub count_100: u16,
+    pub count_50: u16,
+    pub count_geki: u16,
+    pub count_katu: u16,
+    pub count_miss: u16,
+    pub score: u32,
+    pub max_combo: u16,
+    pub perfect: bool,
+    pub mods: u32,
+    pub life_bar: String,
+    pub timestamp: i64,
+    pub frames: Vec<OsrFrame>,
+    pub seed: Option<i32>,
+    pub online_id: i64,
+    pub target_practice: Option<f64>,
+}
+
+// 按 osu! 的二进制格式读取，整数均为小端序
+struct OsrReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> OsrReader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], OsrError> {
+        let (bytes, rest) = self
+            .data
+            .split_first_chunk::<N>()
+            .ok_or(OsrError::Truncated)?;
+        self.data = rest;
+        Ok(*bytes)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], OsrError> {
+        if self.data.len() < len {
+            return Err(OsrError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, OsrError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, OsrError> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, OsrError> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    fn i64(&mut self) -> Result<i64, OsrError> {
+        self.take().map(i64::from_le_bytes)
+    }
+
+    fn f64(&mut self) -> Result<f64, OsrError> {
+        self.take().map(f64::from_le_bytes)
+    }
+
+    fn uleb128(&mut self) -> Result<usize, OsrError> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            // 损坏的数据可能有过多的后续字节
+            if shift >= usize::BITS {
+                return Err(OsrError::InvalidLength);
+            }
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    // 0x00 表示空字符串，0x0b 后接 ULEB128 长度与 UTF-8 内容
+    fn string(&mut self) -> Result<String, OsrError> {
+        match self.u8()? {
+            0x00 => Ok(String::new()),
+            0x0b => {
+                let len = self.uleb128()?;
+                Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+            }
+            mark => Err(OsrError::InvalidString(mark)),
+        }
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, text: &str) {
+    if text.is_empty() {
+        out.push(0x00);
+        return;
+    }
+    out.push(0x0b);
+    let mut len = text.len();
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        match len {
+            0 => {
+                out.push(byte);
+                break;
+            }
+            _ => out.push(byte | 0x80),
+        }
+    }
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn parse_frames(text: &str) -> Result<(Vec<OsrFrame>, Option<i32>), OsrError> {
+    let mut frames = Vec::new();
+    let mut seed = None;
+    for frame in text.split(',').filter(|frame| !frame.trim().is_empty()) {
+        let invalid = || OsrError::InvalidFrame(frame.to_string());
+        let fields: Vec<&str> = frame.split('|').map(str::trim).collect();
+        let [w, x, y, z] = fields[..] else {
+            return Err(invalid());
+        };
+        let delta_ms = w.parse().map_err(|_| invalid())?;
+        if delta_ms == SEED_FRAME {
+            seed = Some(z.parse().map_err(|_| invalid())?);
+            continue;
+        }
+        frames.push(OsrFrame {
+            delta_ms,
+            x: x.parse().map_err(|_| invalid())?,
+            y: y.parse().map_err(|_| invalid())?,
+            keys: z.parse().map_err(|_| invalid())?,
+        });
+    }
+    Ok((frames, seed))
+}
+
+impl Osr {
+    /// 以谱面 MD5 与玩家名创建空的 osu!mania 回放
+    pub fn mania(beatmap_md5: String, player: String) -> Self {
+        Self {
+            mode: MODE_MANIA,
+            version: 20151228,
+            beatmap_md5,
+            player,
+            replay_md5: String::new(),
+            count_300: 0,
+            count_100: 0,
+            count_50: 0,
+            count_geki: 0,
+            count_katu: 0,
+            count_miss: 0,
+            score: 0,
+            max_combo: 0,
+            perfect: false,
+            mods: 0,
+            life_bar: String::new(),
+            timestamp: 0,
+            frames: Vec::new(),
+            seed: None,
+            online_id: 0,
+            target_practice: None,
+        }
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, OsrError> {
+        let mut reader = OsrReader { data };
+        let mut osr = Self {
+            mode: reader.u8()?,
+            version: reader.u32()?,
+            beatmap_md5: reader.string()?,
+            player: reader.string()?,
+            replay_md5: reader.string()?,
+            count_300: reader.u16()?,
+            count_100: reader.u16()?,
+            count_50: reader.u16()?,
+            count_geki: reader.u16()?,
+            count_katu: reader.u16()?,
+            count_miss: reader.u16()?,
+            score: reader.u32()?,
+            max_combo: reader.u16()?,
+            perfect: reader.u8()? != 0,
+            mods: reader.u32()?,
+            life_bar: reader.string()?,
+            timestamp: reader.i64()?,
+            frames: Vec::new(),
+            seed: None,
+            online_id: 0,
+            target_practice: None,
+        };
+        let len = reader.u32()? as usize;
+        let compressed = reader.bytes(len)?;
+        // 没有回放数据的成绩长度为零
+        if !compressed.is_empty() {
+            let mut text = Vec::new();
+            lzma_rs::lzma_decompress(&mut &compressed[..], &mut text).map_err(OsrError::Lzma)?;
+            (osr.frames, osr.seed) = parse_frames(&String::from_utf8_lossy(&text))?;
+        }
+        osr.online_id = reader.i64()?;
+        if osr.mods & MOD_TARGET_PRACTICE != 0 {
+            osr.target_practice = Some(reader.f64()?);
+        }
+        Ok(osr)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, OsrError> {
+        let mut out = vec![self.mode];
+        out.extend_from_slice(&self.version.to_le_bytes());
+        write_string(&mut out, &self.beatmap_md5);
+        write_string(&mut out, &self.player);
+        write_string(&mut out, &self.replay_md5);
+        for count in [
+            self.count_300,
+            self.count_100,
+            self.count_50,
+            self.count_geki,
+            self.count_katu,
+            self.count_miss,
+        ] {
+            out.extend_from_slice(&count.to_le_bytes());
+        }
+        out.extend_from_slice(&self.score.to_le_bytes());
+        out.extend_from_slice(&self.max_combo.to_le_bytes());
+        out.push(self.perfect as u8);
+        out.extend_from_slice(&self.mods.to_le_bytes());
+        write_string(&mut out, &self.life_bar);
+        out.extend_from_slice(&self.timestamp.to_le_bytes());
+
+        let mut text = String::new();
+        for frame in &self.frames {
+            text += &format!("{}|{}|{}|{},", frame.delta_ms, frame.x, frame.y, frame.keys);
+        }
+        if let Some(seed) = self.seed {
+            text += &format!("{SEED_FRAME}|0|0|{seed},");
+        }
+        let mut compressed = Vec::new();
+        lzma_rs::lzma_compress(&mut text.as_bytes(), &mut compressed).map_err(OsrError::Io)?;
+        out.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        out.extend(compressed);
+
+        out.extend_from_slice(&self.online_id.to_le_bytes());
+        if let Some(value) = self.target_practice {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        Ok(out)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, OsrError> {
+        Self::from_bytes(&fs::read(path).map_err(OsrError::Io)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), OsrError> {
+        fs::write(path, self.to_bytes()?).map_err(OsrError::Io)
+    }
+
+    /// 将按键位掩码的变化转换为各轨道的按下与松开事件，只保留前 `key_count` 条轨道
+    ///
+    /// 帧时间为相对音频开始的毫秒数，与谱面时间一致；同一时刻的事件按轨道顺序排列
+    pub fn mania_events(&self, key_count: u8) -> Result<Vec<Event>, OsrError> {
+        if self.mode != MODE_MANIA {
+            return Err(OsrError::NotMania(self.mode));
+        }
+        let mut events = Vec::new();
+        let mut time_ms = 0;
+        let mut held = 0;
+        for (i, frame) in self.frames.iter().enumerate() {
+            time_ms += frame.delta_ms;
+            // stable 在开头写入两帧位置为 (256, -500) 的占位帧，只计时间
+            if i < 2 && frame.x == 256. && frame.y == -500. {
+                continue;
+            }
+            let keys = frame.mania_keys();
+            let changed = keys ^ held;
+            for lane in (0..key_count.min(32)).filter(|lane| changed & (1 << lane) != 0) {
+                let event_ppty = match keys & (1 << lane) != 0 {
+                    true => EventType::Press(lane),
+                    false => EventType::Release(lane),
+                };
+                events.push(Event {
+                    time_stamp: ChartTime::milliseconds(time_ms),
+                    event_ppty,
+                });
+            }
+            held = keys;
+        }
+        Ok(events)
+    }
+
+    /// 由各轨道的按下与松开事件生成回放帧，每个发生变化的毫秒写一帧
+    pub fn set_mania_events(&mut self, events: &[Event]) {
+        let mut frames: Vec<OsrFrame> = Vec::new();
+        let mut last_ms = 0;
+        let mut held = 0u32;
+        for event in events {
+            let bit = match event.event_ppty {
+                EventType::Press(lane) | EventType::Release(lane) if lane < 32 => 1 << lane,
+                _ => continue,
+            };
+            let keys = match event.event_ppty.is_down() {
+                true => held | bit,
+                false => held & !bit,
+            };
+            let time_ms = event.time_stamp.num_milliseconds();
+            match frames.last_mut() {
+                // 同一毫秒内的变化合并为一帧
+                Some(frame) if time_ms == last_ms => *frame = mania_frame(frame.delta_ms, keys),
+                _ => frames.push(mania_frame(time_ms - last_ms, keys)),
+            }
+            last_ms = time_ms;
+            held = keys;
+        }
+        self.frames = frames;
+    }
+
+    /// 转换为 MaiRs 回放，用于送入判定线程
+    pub fn to_replay(
+        &self,
+        key_count: u8,
+        chart_hash: String,
+        profile: JudgementProfile,
+    ) -> Result<Replay, OsrError> {
+        let mut replay = Replay::new(chart_hash, profile);
+        for event in self.mania_events(key_count)? {
+            replay.record(&event);
+        }
+        replay.played_at = self.played_at();
+        replay.mods = vec![format!("osu!:{}", self.mods)];
+        replay
+            .settings
+            .insert("player".to_string(), self.player.clone());
+        replay
+            .settings
+            .insert("beatmap_md5".to_string(), self.beatmap_md5.clone());
+        Ok(replay)
+    }
+
+    /// 游玩日期（RFC 3339）
+    pub fn played_at(&self) -> String {
+        // 0001-01-01 到 1970-01-01 的 ticks
+        const UNIX_EPOCH_TICKS: i64 = 621_355_968_000_000_000;
+        let micros = (self.timestamp - UNIX_EPOCH_TICKS) / 10;
+        chrono::DateTime::from_timestamp_micros(micros)
+            .unwrap_or_default()
+            .to_rfc3339()
+    }
+
+    /// osu! 记录的各判定数量，MAX/300/200/100/50 对应 CriticalPerfect/Perfect/Great/Good/Meh
+    pub fn judgement_counts(&self) -> BTreeMap<Judgement, u32> {
+        [
+            (Judgement::CriticalPerfect, self.count_geki),
+            (Judgement::Perfect, self.count_300),
+            (Judgement::Great, self.count_katu),
+            (Judgement::Good, self.count_100),
+            (Judgement::Meh, self.count_50),
+        ]
+        .into_iter()
+        .map(|(judgement, count)| (judgement, count as u32))
+        .collect()
+    }
+}
+
+fn mania_frame(delta_ms: i64, keys: u32) -> OsrFrame {
+    OsrFrame {
+        delta_ms,
+        x: keys as f32,
+        y: 0.,
+        keys: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ms;
+
+    fn event(at_ms: i64, event_ppty: EventType) -> Event {
+        Event {
+            time_stamp: ms(at_ms),
+            event_ppty,
+        }
+    }
+
+    // Event 没有实现 PartialEq，按时间与事件比较
+    fn timeline(events: &[Event]) -> Vec<(ChartTime, EventType)> {
+        events
+            .iter()
+            .map(|e| (e.time_stamp, e.event_ppty))
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_header_and_frames() {
+        let mut osr = Osr::mania(
+            "0123456789abcdef0123456789abcdef".to_string(),
+            "玩家".repeat(30),
+        );
+        osr.count_geki = 3;
+        osr.count_miss = 1;
+        osr.score = 987_654;
+        osr.timestamp = 638_000_000_000_000_000;
+        osr.seed = Some(7);
+        let events = [
+            event(1000, EventType::Press(0)),
+            event(1000, EventType::Press(2)),
+            event(1080, EventType::Release(0)),
+            event(1200, EventType::Release(2)),
+            event(1500, EventType::Press(3)),
+            event(1600, EventType::Release(3)),
+        ];
+        osr.set_mania_events(&events);
+        assert_eq!(osr.frames.len(), 5);
+
+        let read = Osr::from_bytes(&osr.to_bytes().unwrap()).unwrap();
+        assert_eq!(read, osr);
+        assert_eq!(timeline(&read.mania_events(4).unwrap()), timeline(&events));
+        // 轨道数之外的按键被忽略
+        assert_eq!(read.mania_events(2).unwrap().len(), 2);
+        assert!(read.played_at().starts_with("2022-"));
+        assert!(matches!(
+            Osr::from_bytes(&osr.to_bytes().unwrap()[..40]),
+            Err(OsrError::Truncated)
+        ));
+        let mut corrupt = vec![MODE_MANIA, 0, 0, 0, 0, 0x0b];
+        corrupt.extend([0xff; 12]);
+        assert!(matches!(
+            Osr::from_bytes(&corrupt),
+            Err(OsrError::InvalidLength)
+        ));
+    }
+
+    #[test]
+    fn parses_frames_text() {
+        let (frames, seed) =
+            parse_frames("0|256|-500|0,-1|256|-500|0,1000|5|0|0,-12345|0|0|42,").unwrap();
+        assert_eq!(seed, Some(42));
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[2].mania_keys(), 0b101);
+        assert!(parse_frames("1|2|3").is_err());
+
+        let mut osr = Osr::mania(String::new(), String::new());
+        osr.frames = frames;
+        assert_eq!(
+            timeline(&osr.mania_events(10).unwrap()),
+            timeline(&[
+                event(999, EventType::Press(0)),
+                event(999, EventType::Press(2))
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn judges_mania_replay() {
+        use crate::types::{Widget, WkrType};
+        use std::sync::Arc;
+
+        let profile = JudgementProfile::osu_mania(8.);
+        let shared = Arc::new(profile.clone());
+        let widgets = || {
+            (0..2)
+                .map(|lane| Widget::tap_at(lane as usize, 1000, WkrType::Lane(lane), &shared))
+                .collect()
+        };
+        let mut osr = Osr::mania(String::new(), String::new());
+        osr.set_mania_events(&[
+            event(990, EventType::Press(0)),
+            event(1040, EventType::Release(0)),
+            event(1040, EventType::Press(1)),
+            event(1100, EventType::Release(1)),
+        ]);
+        let replay = osr.to_replay(2, String::new(), profile.clone()).unwrap();
+        let judgements: Vec<_> = replay
+            .play(widgets())
+            .await
+            .iter()
+            .map(|result| result.judgement)
+            .collect();
+        assert_eq!(
+            judgements,
+            [Some(Judgement::CriticalPerfect), Some(Judgement::Perfect)]
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use crate::beatmap::Beatmap;
+use crate::parser::{ParseError, parse_osu};
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+use zip::result::ZipError;
+
+/// 谱面包读取错误
+#[derive(Debug)]
+pub enum OszError {
+    /// 读取失败
+    Io(io::Error),
+    /// 压缩包损坏或格式不支持
+    Zip(ZipError),
+    /// 包内某个难度解析失败
+    Parse { file: String, error: ParseError },
+    /// 谱面引用的文件不在包内
+    MissingFile(String),
+}
+
+impl fmt::Display for OszError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OszError::Io(e) => write!(f, "读取失败: {e}"),
+            OszError::Zip(e) => write!(f, "压缩包无效: {e}"),
+            OszError::Parse { file, error } => write!(f, "{file} {error}"),
+            OszError::MissingFile(file) => write!(f, "谱面包中缺少文件 `{file}`"),
+        }
+    }
+}
+
+impl std::error::Error for OszError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OszError::Io(e) => Some(e),
+            OszError::Zip(e) => Some(e),
+            OszError::Parse { error, .. } => Some(error),
+            OszError::MissingFile(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for OszError {
+    fn from(e: io::Error) -> Self {
+        OszError::Io(e)
+    }
+}
+
+impl From<ZipError> for OszError {
+    fn from(e: ZipError) -> Self {
+        match e {
+            ZipError::Io(e) => OszError::Io(e),
+            e => OszError::Zip(e),
+        }
+    }
+}
+
+/// .osz 谱面包
+///
+/// 一个包内可以有多个 .osu 难度，共用包内的音频与背景图片
+pub struct Osz<R> {
+    archive: ZipArchive<R>,
+    // 包内全部文件名，打开时读取一次
+    names: Vec<String>,
+}
+
+impl Osz<BufReader<File>> {
+    /// 打开文件系统中的谱面包
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, OszError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> Osz<R> {
+    /// 从任意可随机读取的数据源打开谱面包
+    pub fn new(reader: R) -> Result<Self, OszError> {
+        let archive = ZipArchive::new(reader)?;
+        let names = archive
+            .file_names()
+            .map(|name| name.map(String::from))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { archive, names })
+    }
+
+    /// 包内所有 .osu 难度的文件名
+    pub fn difficulties(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .names
+            .iter()
+            .filter(|name| name.to_ascii_lowercase().ends_with(".osu"))
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
+
+    // 按 osu! 的习惯忽略大小写与路径分隔符查找文件
+    fn find(&self, name: &str) -> Result<String, OszError> {
+        let wanted = name.replace('\\', "/");
+        self.names
+            .iter()
+            .find(|entry| entry.eq_ignore_ascii_case(&wanted))
+            .cloned()
+            .ok_or_else(|| OszError::MissingFile(name.to_string()))
+    }
+
+    /// 解析包内的某个难度
+    pub fn beatmap(&mut self, name: &str) -> Result<Beatmap, OszError> {
+        let name = self.find(name)?;
+        let entry = self.archive.by_name(&name)?;
+        parse_osu(entry).map_err(|error| OszError::Parse { file: name, error })
+    }
+
+    /// 解析包内的全部难度，顺序与 `difficulties` 相同
+    pub fn beatmaps(&mut self) -> Result<Vec<(String, Beatmap)>, OszError> {
+        self.difficulties()
+            .into_iter()
+            .map(|name| Ok((name.clone(), self.beatmap(&name)?)))
+            .collect()
+    }
+
+    /// 读取包内的任意文件
+    pub fn read_file(&mut self, name: &str) -> Result<Vec<u8>, OszError> {
+        let name = self.find(name)?;
+        let mut entry = self.archive.by_name(&name)?;
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// 读取谱面引用的音频文件
+    pub fn audio(&mut self, beatmap: &Beatmap) -> Result<Vec<u8>, OszError> {
+        self.read_file(&beatmap.general.audio_filename)
+    }
+
+    /// 读取谱面引用的背景图片，谱面没有背景时返回 `None`
+    pub fn background(&mut self, beatmap: &Beatmap) -> Result<Option<Vec<u8>>, OszError> {
+        beatmap
+            .background()
+            .map(|filename| self.read_file(filename))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::ZipWriter;
+    use zip::write::SimpleFileOptions;
+
+    fn build_osz(files: &[(&str, &[u8])]) -> Cursor<Vec<u8>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let mut cursor = writer.finish().unwrap();
+        cursor.set_position(0);
+        cursor
+    }
+
+    #[test]
+    fn load_difficulties_and_assets() {
+        let chart = include_str!("bin/test.txt");
+        let mut osz = Osz::new(build_osz(&[
+            ("Song [Hard].osu", chart.as_bytes()),
+            ("Song [Easy].osu", chart.as_bytes()),
+            ("audio.mp3", b"audio"),
+        ]))
+        .unwrap();
+        assert_eq!(osz.difficulties(), ["Song [Easy].osu", "Song [Hard].osu"]);
+
+        let beatmaps = osz.beatmaps().unwrap();
+        assert_eq!(beatmaps.len(), 2);
+        let beatmap = &beatmaps[0].1;
+        assert_eq!(beatmap.hit_objects.len(), 825);
+
+        let mut beatmap = beatmap.clone();
+        beatmap.general.audio_filename = "Audio.MP3".to_string();
+        assert_eq!(osz.audio(&beatmap).unwrap(), b"audio");
+    }
+
+    #[test]
+    fn missing_asset_and_bad_chart() {
+        let mut osz = Osz::new(build_osz(&[("bad.osu", b"not a chart")])).unwrap();
+        assert!(matches!(osz.read_file("bg.jpg"), Err(OszError::MissingFile(f)) if f == "bg.jpg"));
+        match osz.beatmap("bad.osu") {
+            Err(OszError::Parse { file, error }) => {
+                assert_eq!(file, "bad.osu");
+                assert_eq!(error.line, 1);
+            }
+            other => panic!("{other:?}"),
+        }
+    }
+}
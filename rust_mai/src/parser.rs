@@ -1,111 +1,525 @@
-use crate::types::{Widget, WidgetForDisplay, WkrType};
-use chrono::{DateTime, Duration, Utc};
+use crate::beatmap::*;
 
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, Read};
 use std::path::Path;
+use std::str::FromStr;
 
-// 解析osu文件并生成组件
-pub fn parse_osu_file<P: AsRef<Path>>(
-    file_path: P,
-    base_time: DateTime<Utc>,
-    screen_height: f64,
-    scroll_speed: f64,
-) -> (Vec<Widget>, Vec<WidgetForDisplay>) {
-    let file = File::open(file_path).expect("无法打开文件");
-    let reader = BufReader::new(file);
+/// 谱面解析错误
+///
+/// `line` 与 `column` 均从1开始计数，指向出错的字段。为0表示没有对应的位置：
+/// 打开或读取文件失败时行列均为0，读取某一行失败时列为0
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: ParseErrorKind,
+}
+
+/// 谱面解析错误类型
+#[derive(Debug)]
+pub enum ParseErrorKind {
+    /// 读取失败
+    Io(io::Error),
+    /// 缺少 `osu file format vN` 文件头
+    MissingHeader,
+    /// 缺少必需字段
+    MissingField(&'static str),
+    /// 字段值无法解析
+    InvalidValue { field: &'static str, value: String },
+    /// 键值对缺少 `:`
+    InvalidKeyValue,
+    /// 无法识别的音符写法
+    InvalidNote(String),
+    /// 括号未闭合
+    Unclosed(char),
+    /// 出现音符前未设置BPM
+    MissingBpm,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (0, _) => {}
+            (line, 0) => write!(f, "第{line}行: ")?,
+            (line, column) => write!(f, "第{line}行第{column}列: ")?,
+        }
+        match &self.kind {
+            ParseErrorKind::Io(e) => write!(f, "读取失败: {e}"),
+            ParseErrorKind::MissingHeader => write!(f, "缺少文件头 `osu file format vN`"),
+            ParseErrorKind::MissingField(field) => write!(f, "缺少字段 `{field}`"),
+            ParseErrorKind::InvalidValue { field, value } => {
+                write!(f, "字段 `{field}` 的值 `{value}` 无效")
+            }
+            ParseErrorKind::InvalidKeyValue => write!(f, "应为 `键:值` 格式"),
+            ParseErrorKind::InvalidNote(note) => write!(f, "无法识别的音符 `{note}`"),
+            ParseErrorKind::Unclosed(c) => write!(f, "缺少 `{c}`"),
+            ParseErrorKind::MissingBpm => write!(f, "未设置BPM"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ParseErrorKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
-    let mut widgets = Vec::new();
-    let mut widgets_for_display = Vec::new();
-    let mut id_counter = 0;
+// 当前所在段落
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Section {
+    None,
+    General,
+    Metadata,
+    Difficulty,
+    Events,
+    TimingPoints,
+    HitObjects,
+    // Editor、Colours 等与游玩无关的段落
+    Ignored,
+}
 
-    // 假设的屏幕高度和流速（这些值可能需要根据实际情况调整）
-    // let screen_height = 384.0; // 像素
-    // let scroll_speed = 1.0; // 流速乘数
+// 一行文本及其行号，提供带定位信息的字段解析
+struct Line<'a> {
+    number: usize,
+    text: &'a str,
+}
 
-    // 基准时间（1970年1月1日）
-    // let base_time = Utc.ymd(1970, 1, 1).and_hms(0, 0, 0);
-    // let base_time = Utc::now();
+impl<'a> Line<'a> {
+    fn error(&self, column: usize, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            line: self.number,
+            column,
+            kind,
+        }
+    }
 
-    let mut in_hit_objects = false;
+    // 子串在行内的列号
+    fn column_of(&self, part: &str) -> usize {
+        let offset = part.as_ptr() as usize - self.text.as_ptr() as usize;
+        self.text[..offset].chars().count() + 1
+    }
 
-    for line in reader.lines() {
-        let line = line.expect("读取行失败");
+    fn parse<T: FromStr>(&self, part: &str, field: &'static str) -> Result<T, ParseError> {
+        part.trim().parse().map_err(|_| {
+            self.error(
+                self.column_of(part),
+                ParseErrorKind::InvalidValue {
+                    field,
+                    value: part.to_string(),
+                },
+            )
+        })
+    }
+
+    fn parse_bool(&self, part: &str, field: &'static str) -> Result<bool, ParseError> {
+        Ok(self.parse::<i32>(part, field)? != 0)
+    }
+
+    // 按逗号切分，至少需要 `fields.len()` 个字段
+    fn split_fields(&self, fields: &[&'static str]) -> Result<Vec<&'a str>, ParseError> {
+        let parts: Vec<&str> = self.text.split(',').collect();
+        if parts.len() < fields.len() {
+            return Err(self.error(
+                self.text.chars().count() + 1,
+                ParseErrorKind::MissingField(fields[parts.len()]),
+            ));
+        }
+        Ok(parts)
+    }
+
+    fn key_value(&self) -> Result<(&'a str, &'a str), ParseError> {
+        self.text
+            .split_once(':')
+            .map(|(k, v)| (k.trim(), v.trim()))
+            .ok_or_else(|| self.error(1, ParseErrorKind::InvalidKeyValue))
+    }
+}
+
+/// 从文件解析osu谱面
+pub fn parse_osu_file<P: AsRef<Path>>(file_path: P) -> Result<Beatmap, ParseError> {
+    let file = File::open(file_path).map_err(|e| ParseError {
+        line: 0,
+        column: 0,
+        kind: ParseErrorKind::Io(e),
+    })?;
+    parse_osu(file)
+}
+
+/// 从任意数据源解析osu谱面，例如压缩包中的条目
+pub fn parse_osu<R: Read>(reader: R) -> Result<Beatmap, ParseError> {
+    parse_lines(BufReader::new(reader).lines())
+}
+
+/// 从字符串解析osu谱面
+pub fn parse_osu_str(content: &str) -> Result<Beatmap, ParseError> {
+    parse_lines(content.lines().map(|l| Ok(l.to_string())))
+}
+
+fn parse_lines<I: Iterator<Item = io::Result<String>>>(lines: I) -> Result<Beatmap, ParseError> {
+    let mut beatmap = Beatmap {
+        format_version: 0,
+        general: General::default(),
+        metadata: Metadata::default(),
+        difficulty: Difficulty::default(),
+        events: Vec::new(),
+        timing_points: Vec::new(),
+        hit_objects: Vec::new(),
+    };
+    let mut section = Section::None;
+    let mut header_found = false;
+
+    for (index, text) in lines.enumerate() {
+        let number = index + 1;
+        let text = text.map_err(|e| ParseError {
+            line: number,
+            column: 0,
+            kind: ParseErrorKind::Io(e),
+        })?;
+        let text = text.trim_end().trim_start_matches('\u{feff}');
+        let line = Line { number, text };
 
         // 跳过注释和空行
-        if line.trim().is_empty() || line.trim().starts_with("//") {
+        if text.trim().is_empty() || text.trim_start().starts_with("//") {
             continue;
         }
 
-        // 检查是否进入HitObjects部分
-        if line.trim() == "[HitObjects]" {
-            in_hit_objects = true;
+        if !header_found {
+            let version = text
+                .trim()
+                .strip_prefix("osu file format v")
+                .ok_or_else(|| line.error(1, ParseErrorKind::MissingHeader))?;
+            beatmap.format_version = line.parse(version, "format version")?;
+            header_found = true;
             continue;
         }
 
-        // 如果不在HitObjects部分，继续读取
-        if !in_hit_objects {
+        // 段落标题
+        if let Some(name) = text
+            .trim()
+            .strip_prefix('[')
+            .and_then(|s| s.strip_suffix(']'))
+        {
+            section = match name {
+                "General" => Section::General,
+                "Metadata" => Section::Metadata,
+                "Difficulty" => Section::Difficulty,
+                "Events" => Section::Events,
+                "TimingPoints" => Section::TimingPoints,
+                "HitObjects" => Section::HitObjects,
+                _ => Section::Ignored,
+            };
             continue;
         }
 
-        // 解析HitObject行
-        let parts: Vec<&str> = line.split(',').collect();
-        if parts.len() < 4 {
-            continue;
+        match section {
+            Section::General => parse_general(&line, &mut beatmap.general)?,
+            Section::Metadata => parse_metadata(&line, &mut beatmap.metadata)?,
+            Section::Difficulty => parse_difficulty(&line, &mut beatmap.difficulty)?,
+            Section::Events => beatmap.events.push(parse_event(&line)?),
+            Section::TimingPoints => beatmap.timing_points.push(parse_timing_point(&line)?),
+            Section::HitObjects => beatmap.hit_objects.push(parse_hit_object(&line)?),
+            Section::None | Section::Ignored => {}
         }
+    }
 
-        let x = parts[0].parse::<i32>().unwrap_or(0);
-        let time_ms = parts[2].parse::<i64>().unwrap_or(0);
-        let obj_type = parts[3].parse::<i32>().unwrap_or(0);
-
-        // 只处理普通点击和长条的起点（忽略长条的中间部分）
-        if obj_type & 1 == 1 || obj_type & 128 == 128 {
-            // 将x坐标映射到WkrType
-            let wkr_type = match x {
-                64 => WkrType::Wkr1,
-                192 => WkrType::Wkr2,
-                320 => WkrType::Wkr3,
-                448 => WkrType::Wkr4,
-                _ => continue, // 跳过不在预期位置的音符
-            };
+    if !header_found {
+        return Err(ParseError {
+            line: 1,
+            column: 1,
+            kind: ParseErrorKind::MissingHeader,
+        });
+    }
 
-            // 计算时间戳
-            let hit_time = Duration::milliseconds(time_ms);
-            let widget_time = base_time + hit_time - Duration::milliseconds(1000); // 提前20ms
-
-            // 计算显示时间（基于流速和屏幕高度）
-            // 这里简化计算，实际可能需要更复杂的公式
-            let approach_time_ms = screen_height / scroll_speed; // 假设AR为5对应的接近时间
-            let display_time =
-                base_time + hit_time - Duration::milliseconds(approach_time_ms as i64);
-
-            // 创建Widget
-            let widget = Widget {
-                id: id_counter,
-                time_stamp: widget_time,
-                wkr_ppty: wkr_type,
-            };
+    Ok(beatmap)
+}
 
-            // 创建WidgetForDisplay
-            let widget_display = WidgetForDisplay {
-                id: id_counter,
-                time_stamp_general: if widget_time < display_time {
-                    widget_time
-                } else {
-                    display_time
-                },
-                time_stamp_display: display_time,
-                time_hit: base_time + hit_time,
-                place: wkr_type,
-                deleted: false,
-            };
+fn parse_general(line: &Line, general: &mut General) -> Result<(), ParseError> {
+    let (key, value) = line.key_value()?;
+    match key {
+        "AudioFilename" => general.audio_filename = value.to_string(),
+        "AudioLeadIn" => general.audio_lead_in = line.parse(value, "AudioLeadIn")?,
+        "PreviewTime" => general.preview_time = line.parse(value, "PreviewTime")?,
+        "Countdown" => general.countdown = line.parse(value, "Countdown")?,
+        "SampleSet" => general.sample_set = value.to_string(),
+        "StackLeniency" => general.stack_leniency = line.parse(value, "StackLeniency")?,
+        "Mode" => general.mode = line.parse(value, "Mode")?,
+        "LetterboxInBreaks" => {
+            general.letterbox_in_breaks = line.parse_bool(value, "LetterboxInBreaks")?
+        }
+        "SpecialStyle" => general.special_style = line.parse_bool(value, "SpecialStyle")?,
+        "WidescreenStoryboard" => {
+            general.widescreen_storyboard = line.parse_bool(value, "WidescreenStoryboard")?
+        }
+        _ => general.extra.push((key.to_string(), value.to_string())),
+    }
+    Ok(())
+}
+
+fn parse_metadata(line: &Line, metadata: &mut Metadata) -> Result<(), ParseError> {
+    let (key, value) = line.key_value()?;
+    match key {
+        "Title" => metadata.title = value.to_string(),
+        "TitleUnicode" => metadata.title_unicode = value.to_string(),
+        "Artist" => metadata.artist = value.to_string(),
+        "ArtistUnicode" => metadata.artist_unicode = value.to_string(),
+        "Creator" => metadata.creator = value.to_string(),
+        "Version" => metadata.version = value.to_string(),
+        "Source" => metadata.source = value.to_string(),
+        "Tags" => metadata.tags = value.split_whitespace().map(str::to_string).collect(),
+        "BeatmapID" => metadata.beatmap_id = Some(line.parse(value, "BeatmapID")?),
+        "BeatmapSetID" => metadata.beatmap_set_id = Some(line.parse(value, "BeatmapSetID")?),
+        _ => {}
+    }
+    Ok(())
+}
+
+fn parse_difficulty(line: &Line, difficulty: &mut Difficulty) -> Result<(), ParseError> {
+    let (key, value) = line.key_value()?;
+    match key {
+        "HPDrainRate" => difficulty.hp_drain_rate = line.parse(value, "HPDrainRate")?,
+        "CircleSize" => difficulty.circle_size = line.parse(value, "CircleSize")?,
+        "OverallDifficulty" => {
+            difficulty.overall_difficulty = line.parse(value, "OverallDifficulty")?
+        }
+        "ApproachRate" => difficulty.approach_rate = line.parse(value, "ApproachRate")?,
+        "SliderMultiplier" => {
+            difficulty.slider_multiplier = line.parse(value, "SliderMultiplier")?
+        }
+        "SliderTickRate" => difficulty.slider_tick_rate = line.parse(value, "SliderTickRate")?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn parse_event(line: &Line) -> Result<BeatmapEvent, ParseError> {
+    let parts: Vec<&str> = line.text.split(',').collect();
+    let unquote = |s: &str| s.trim().trim_matches('"').to_string();
+    let offset = |i: usize, field| -> Result<i32, ParseError> {
+        parts.get(i).map_or(Ok(0), |p| line.parse(p, field))
+    };
+    let event = match parts[0].trim() {
+        "0" | "Background" if parts.len() >= 3 => BeatmapEvent::Background {
+            filename: unquote(parts[2]),
+            x: offset(3, "xOffset")?,
+            y: offset(4, "yOffset")?,
+        },
+        "1" | "Video" if parts.len() >= 3 => BeatmapEvent::Video {
+            start_time: line.parse(parts[1], "startTime")?,
+            filename: unquote(parts[2]),
+            x: offset(3, "xOffset")?,
+            y: offset(4, "yOffset")?,
+        },
+        "2" | "Break" if parts.len() >= 3 => BeatmapEvent::Break {
+            start_time: line.parse(parts[1], "startTime")?,
+            end_time: line.parse(parts[2], "endTime")?,
+        },
+        _ => BeatmapEvent::Other(line.text.to_string()),
+    };
+    Ok(event)
+}
 
-            widgets.push(widget);
-            widgets_for_display.push(widget_display);
+fn parse_timing_point(line: &Line) -> Result<TimingPoint, ParseError> {
+    let parts = line.split_fields(&["time", "beatLength"])?;
+    let opt = |i: usize, field, default| -> Result<i32, ParseError> {
+        parts.get(i).map_or(Ok(default), |p| line.parse(p, field))
+    };
+    Ok(TimingPoint {
+        time: line.parse(parts[0], "time")?,
+        beat_length: line.parse(parts[1], "beatLength")?,
+        meter: opt(2, "meter", 4)?,
+        sample_set: opt(3, "sampleSet", 0)?,
+        sample_index: opt(4, "sampleIndex", 0)?,
+        volume: opt(5, "volume", 100)?,
+        uninherited: opt(6, "uninherited", 1)? != 0,
+        effects: opt(7, "effects", 0)?,
+    })
+}
 
-            id_counter += 1;
+fn parse_hit_sample(line: &Line, part: &str) -> Result<HitSample, ParseError> {
+    let fields: Vec<&str> = part.split(':').collect();
+    let num = |i: usize, field| -> Result<i32, ParseError> {
+        match fields.get(i) {
+            Some(p) if !p.trim().is_empty() => line.parse(p, field),
+            _ => Ok(0),
         }
+    };
+    Ok(HitSample {
+        normal_set: num(0, "normalSet")?,
+        addition_set: num(1, "additionSet")?,
+        index: num(2, "index")?,
+        volume: num(3, "volume")?,
+        filename: fields.get(4).map_or(String::new(), |s| s.to_string()),
+    })
+}
+
+fn parse_hit_object(line: &Line) -> Result<HitObject, ParseError> {
+    let parts = line.split_fields(&["x", "y", "time", "type", "hitSound"])?;
+    let object_type: i32 = line.parse(parts[3], "type")?;
+    let params = &parts[5..];
+
+    let (kind, sample_part) = if object_type & 128 != 0 {
+        // 长条: endTime:hitSample
+        let extras = params.first().ok_or_else(|| {
+            line.error(
+                line.text.chars().count() + 1,
+                ParseErrorKind::MissingField("endTime"),
+            )
+        })?;
+        let (end_time, sample) = extras.split_once(':').unwrap_or((extras, ""));
+        (
+            HitObjectKind::Hold {
+                end_time: line.parse(end_time, "endTime")?,
+            },
+            Some(sample),
+        )
+    } else if object_type & 8 != 0 {
+        let end_time = params.first().ok_or_else(|| {
+            line.error(
+                line.text.chars().count() + 1,
+                ParseErrorKind::MissingField("endTime"),
+            )
+        })?;
+        (
+            HitObjectKind::Spinner {
+                end_time: line.parse(end_time, "endTime")?,
+            },
+            params.get(1).copied(),
+        )
+    } else if object_type & 2 != 0 {
+        // 滑条: hitSample 位于 curve、slides、length、edgeSounds、edgeSets 之后
+        let (slider_params, sample) = match params.split_last() {
+            Some((last, rest)) if params.len() > 5 && last.contains(':') => (rest, Some(*last)),
+            _ => (params, None),
+        };
+        (
+            HitObjectKind::Slider {
+                params: slider_params.join(","),
+            },
+            sample,
+        )
+    } else {
+        (HitObjectKind::Circle, params.first().copied())
+    };
+
+    Ok(HitObject {
+        x: line.parse(parts[0], "x")?,
+        y: line.parse(parts[1], "y")?,
+        time: line.parse(parts[2], "time")?,
+        kind,
+        new_combo: object_type & 4 != 0,
+        combo_skip: ((object_type >> 4) & 0b111) as u8,
+        hit_sound: line.parse(parts[4], "hitSound")?,
+        hit_sample: match sample_part {
+            Some(part) => parse_hit_sample(line, part)?,
+            None => HitSample::default(),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_MAP: &str = include_str!("bin/test.txt");
+
+    #[test]
+    fn parse_test_map() {
+        let beatmap = parse_osu_str(TEST_MAP).unwrap();
+        assert_eq!(beatmap.format_version, 14);
+        assert_eq!(beatmap.general.audio_filename, "audio.ogg");
+        assert_eq!(beatmap.general.mode, 3);
+        assert_eq!(beatmap.metadata.version, "BASIC");
+        assert_eq!(beatmap.metadata.beatmap_id, Some(4966460));
+        assert_eq!(beatmap.difficulty.circle_size, 4.);
+        assert_eq!(beatmap.background(), Some("background.png"));
+        assert_eq!(beatmap.timing_points.len(), 23);
+        assert_eq!(beatmap.timing_points[0].bpm(), Some(240.));
+        assert_eq!(beatmap.hit_objects.len(), 825);
+        assert_eq!(
+            beatmap.hit_objects[4].kind,
+            HitObjectKind::Hold { end_time: 2111 }
+        );
     }
 
-    (widgets, widgets_for_display)
+    #[test]
+    fn invalid_field_reports_position() {
+        let content =
+            "osu file format v14\n\n[HitObjects]\n64,192,736,1,0,0:0:0:0:\n64,192,7x6,1,0\n";
+        let error = parse_osu_str(content).unwrap_err();
+        assert_eq!((error.line, error.column), (5, 8));
+        assert!(matches!(
+            error.kind,
+            ParseErrorKind::InvalidValue { field: "time", .. }
+        ));
+        assert!(error.to_string().starts_with("第5行第8列: "));
+
+        // 打开失败没有位置
+        let error = parse_osu_file("/nonexistent/map.osu").unwrap_err();
+        assert_eq!((error.line, error.column), (0, 0));
+        assert!(error.to_string().starts_with("读取失败"));
+    }
+
+    #[test]
+    fn seven_key_columns() {
+        let content = "osu file format v14\n[Difficulty]\nCircleSize:7\n[HitObjects]\n\
+                       36,192,100,1,0\n109,192,200,1,0\n256,192,300,1,0\n475,192,400,1,0\n";
+        let beatmap = parse_osu_str(content).unwrap();
+        assert_eq!(beatmap.key_count(), 7);
+        let (widgets, _) = beatmap.build_widgets(&beatmap.judgement_profile().into(), 1200., 20.);
+        let lanes: Vec<_> = widgets.iter().map(|w| w.wkr_ppty).collect();
+        assert_eq!(
+            lanes,
+            [0, 1, 3, 6].map(crate::types::WkrType::Lane).to_vec()
+        );
+    }
+
+    #[test]
+    fn display_time_follows_scroll_velocity() {
+        // 1000ms 起两倍流速，2000ms 的音符位于卷轴位置 3000
+        let content = "osu file format v14\n[TimingPoints]\n0,500,4,1,0,100,1,0\n\
+                       1000,-50,4,1,0,100,0,0\n[HitObjects]\n64,192,2000,1,0\n";
+        let beatmap = parse_osu_str(content).unwrap();
+        // 每个位置单位2像素，1000像素的屏幕对应500的卷轴距离，回退到位置 2500 即 1750ms
+        let (_, display) = beatmap.build_widgets(&beatmap.judgement_profile().into(), 1000., 2.);
+        assert_eq!(display[0].position, 3000.);
+        assert_eq!(
+            display[0].time_stamp_display,
+            crate::types::ChartTime::milliseconds(1750)
+        );
+    }
+
+    #[test]
+    fn slider_edge_sets_are_not_hit_sample() {
+        let content = "osu file format v14\n[HitObjects]\n\
+                       64,192,1000,2,0,B|100:100|200:200,1,100,2|0,0:0|0:0\n\
+                       64,192,2000,2,0,B|100:100,1,100,2|0,0:0|0:0,1:2:0:0:\n";
+        let beatmap = parse_osu_str(content).unwrap();
+        let params: Vec<_> = beatmap
+            .hit_objects
+            .iter()
+            .map(|object| match &object.kind {
+                HitObjectKind::Slider { params } => params.as_str(),
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            params,
+            [
+                "B|100:100|200:200,1,100,2|0,0:0|0:0",
+                "B|100:100,1,100,2|0,0:0|0:0"
+            ]
+        );
+        assert_eq!(beatmap.hit_objects[1].hit_sample.normal_set, 1);
+    }
+
+    #[test]
+    fn missing_header() {
+        let error = parse_osu_str("[General]\nMode: 3\n").unwrap_err();
+        assert!(matches!(error.kind, ParseErrorKind::MissingHeader));
+    }
 }
@@ -0,0 +1,483 @@
+use crate::clock::SharedClock;
+use crate::held::HeldLanes;
+use crate::judgement::JudgementProfile;
+use crate::types::{
+    ChartTime, Event, EventType, Judgement, NotePart, RtV, Widget, WidgetKind, WkrType,
+    worker_properties,
+};
+
+use general_time_event_driven::types::{MissRecord, RuntimeEvent, WidgetTrait};
+use general_time_event_driven::worker_pool::WorkerPool;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// 当前的回放格式版本
+pub const REPLAY_VERSION: u32 = 1;
+
+// 二进制格式的文件头
+const BINARY_MAGIC: &[u8; 4] = b"MAIP";
+
+// 回放时时钟事件的间隔，与游玩时时钟线程的频率相近
+const TICK_MS: i64 = 100;
+
+// 离线判定时允许积压的事件数，远小于判定线程广播通道的容量
+const MAX_BACKLOG: usize = 64;
+
+/// 回放读写错误
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Binary(postcard::Error),
+    /// 二进制数据缺少文件头
+    InvalidMagic,
+    /// 文件版本高于当前支持的版本
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "读写失败: {e}"),
+            ReplayError::Json(e) => write!(f, "JSON格式错误: {e}"),
+            ReplayError::Binary(e) => write!(f, "二进制格式错误: {e}"),
+            ReplayError::InvalidMagic => write!(f, "不是MaiRs回放文件"),
+            ReplayError::UnsupportedVersion(version) => {
+                write!(f, "不支持的回放版本 {version}，当前版本为 {REPLAY_VERSION}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+fn check_version(version: u32) -> Result<(), ReplayError> {
+    match version {
+        1..=REPLAY_VERSION => Ok(()),
+        _ => Err(ReplayError::UnsupportedVersion(version)),
+    }
+}
+
+/// 谱面内容的 SHA-256，用于确认回放与谱面对应
+pub fn chart_hash<T: Serialize>(chart: &T) -> String {
+    let json = serde_json::to_vec(chart).expect("谱面可以序列化");
+    Sha256::digest(json)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// 一个输入事件，时间为谱面时间（微秒）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayFrame {
+    pub time_us: i64,
+    pub event: EventType,
+}
+
+impl ReplayFrame {
+    pub fn time(&self) -> ChartTime {
+        ChartTime::microseconds(self.time_us)
+    }
+
+    pub fn to_event(self) -> Event {
+        Event {
+            time_stamp: self.time(),
+            event_ppty: self.event,
+        }
+    }
+}
+
+impl From<&Event> for ReplayFrame {
+    fn from(event: &Event) -> Self {
+        Self {
+            time_us: event.time_stamp.num_microseconds().unwrap_or(i64::MAX),
+            event: event.event_ppty,
+        }
+    }
+}
+
+/// 一局的回放
+///
+/// 记录全部输入事件与重现判定所需的谱面哈希、判定配置、Mod 与设置。
+/// `played_at` 为游玩时的日期（RFC 3339），只作为记录
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub chart_hash: String,
+    pub mods: Vec<String>,
+    pub profile: JudgementProfile,
+    #[serde(default)]
+    pub settings: BTreeMap<String, String>,
+    pub played_at: String,
+    pub frames: Vec<ReplayFrame>,
+}
+
+impl Replay {
+    pub fn new(chart_hash: String, profile: JudgementProfile) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            chart_hash,
+            mods: Vec::new(),
+            profile,
+            settings: BTreeMap::new(),
+            played_at: chrono::Utc::now().to_rfc3339(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// 记录输入事件，时钟事件不记录
+    pub fn record(&mut self, event: &Event) {
+        if event.event_ppty != EventType::All {
+            self.frames.push(event.into());
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, ReplayError> {
+        serde_json::to_string_pretty(self).map_err(ReplayError::Json)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, ReplayError> {
+        let replay: Self = serde_json::from_str(text).map_err(ReplayError::Json)?;
+        check_version(replay.version)?;
+        Ok(replay)
+    }
+
+    /// 紧凑的二进制形式：文件头、版本号与 postcard 编码的回放
+    pub fn to_binary(&self) -> Result<Vec<u8>, ReplayError> {
+        let mut data = BINARY_MAGIC.to_vec();
+        data.extend_from_slice(&self.version.to_le_bytes());
+        data.extend(postcard::to_stdvec(self).map_err(ReplayError::Binary)?);
+        Ok(data)
+    }
+
+    pub fn from_binary(data: &[u8]) -> Result<Self, ReplayError> {
+        let body = data
+            .strip_prefix(BINARY_MAGIC)
+            .ok_or(ReplayError::InvalidMagic)?;
+        let (version, body) = body
+            .split_first_chunk::<4>()
+            .ok_or(ReplayError::InvalidMagic)?;
+        check_version(u32::from_le_bytes(*version))?;
+        postcard::from_bytes(body).map_err(ReplayError::Binary)
+    }
+
+    /// 写入文件，扩展名为 `.json` 时使用 JSON，否则使用二进制形式
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ReplayError> {
+        let data = match is_json(path.as_ref()) {
+            true => self.to_json()?.into_bytes(),
+            false => self.to_binary()?,
+        };
+        fs::write(path, data).map_err(ReplayError::Io)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ReplayError> {
+        let data = fs::read(path.as_ref()).map_err(ReplayError::Io)?;
+        match is_json(path.as_ref()) {
+            true => Self::from_json(&String::from_utf8_lossy(&data)),
+            false => Self::from_binary(&data),
+        }
+    }
+
+    /// 最后一个输入事件的时间
+    pub fn duration(&self) -> ChartTime {
+        self.frames
+            .last()
+            .map_or(ChartTime::zero(), ReplayFrame::time)
+    }
+
+    /// 将输入送入新的 `WorkerPool`，返回按时间排序的判定结果
+    ///
+    /// 时间完全由记录的时间戳决定，不读取真实时钟：输入之间每隔 `TICK_MS` 插入时钟事件，
+    /// 最后以一个晚于所有音符的时钟事件使剩余音符过期。同一回放与组件总是得到相同的结果
+    pub async fn play(&self, widgets: Vec<Widget>) -> Vec<NoteResult> {
+        let mut pending: HashSet<usize> = widgets.iter().map(|widget| widget.id).collect();
+        let end = widgets
+            .iter()
+            .map(last_deadline)
+            .max()
+            .unwrap_or_default()
+            .max(self.duration())
+            + ChartTime::milliseconds(TICK_MS);
+        let wkr_ppty_vec = worker_properties(widgets.iter().map(|w| w.get_worker_property()));
+
+        let (rt_sndr, mut rt_rcvr) = mpsc::channel(1000);
+        let (event_sndr, pool) = WorkerPool::build(wkr_ppty_vec, widgets, rt_sndr).await;
+
+        let frames = self.frames.clone();
+        let feeder = tokio::spawn(async move {
+            let send = async |event| send_paced(&event_sndr, &pool, event).await;
+            let mut tick = ChartTime::zero();
+            for event in frames.into_iter().map(ReplayFrame::to_event) {
+                while tick < event.time_stamp {
+                    send(tick_event(tick)).await;
+                    tick += ChartTime::milliseconds(TICK_MS);
+                }
+                send(event).await;
+            }
+            send(tick_event(end)).await;
+            (event_sndr, pool)
+        });
+
+        let mut results = Vec::new();
+        while !pending.is_empty()
+            && let Some(event) = rt_rcvr.recv().await
+        {
+            if let Some(result) = NoteResult::from_event(&event) {
+                // 长条头部之后还有尾部
+                if result.part != Some(NotePart::HoldHead) {
+                    pending.remove(&result.id);
+                }
+                results.push(result);
+            }
+        }
+        feeder.abort();
+        results.sort_by_key(NoteResult::sort_key);
+        results
+    }
+}
+
+// 组件最晚可判定的时刻，长条与星星以结束时刻计
+fn last_deadline(widget: &Widget) -> ChartTime {
+    let judged_at = match widget.kind {
+        WidgetKind::Hold { time_end, .. } | WidgetKind::Slide { time_end, .. } => time_end,
+        WidgetKind::Tap => widget.time_hit,
+    };
+    judged_at + widget.profile.latest()
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+fn tick_event(time_stamp: ChartTime) -> Event {
+    Event {
+        time_stamp,
+        event_ppty: EventType::All,
+    }
+}
+
+// 积压的事件达到 `MAX_BACKLOG` 时等待判定线程接收，避免落后的工作线程跳过事件
+async fn send_paced(
+    sndr: &general_time_event_driven::event_queue::Sender<Event>,
+    pool: &WorkerPool<Event, Widget>,
+    event: Event,
+) {
+    while sndr.len() + pool.backlog() >= MAX_BACKLOG {
+        tokio::task::yield_now().await;
+    }
+    sndr.send(event).await;
+}
+
+/// 一个判定对象的结果，`judgement` 为 `None` 表示 Miss
+///
+/// 时间均为谱面时间（微秒）：击中时为输入事件的时间，Miss 时为音符的判定时刻
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NoteResult {
+    pub id: usize,
+    pub part: Option<NotePart>,
+    pub judgement: Option<Judgement>,
+    pub offset_us: Option<i64>,
+    pub time_us: i64,
+    pub place: WkrType,
+}
+
+impl NoteResult {
+    /// 从判定结果转换，空返回值返回 `None`
+    pub fn from_event(event: &RuntimeEvent<RtV>) -> Option<Self> {
+        let micros = |t: ChartTime| t.num_microseconds().unwrap_or(i64::MAX);
+        match event {
+            RuntimeEvent::Some(rtv) if !rtv.is_blank => Some(Self {
+                id: rtv.id,
+                part: Some(rtv.part),
+                judgement: Some(rtv.judgement),
+                offset_us: Some(micros(rtv.offset)),
+                time_us: micros(rtv.time_stamp),
+                place: rtv.place,
+            }),
+            RuntimeEvent::Missed(miss) => Some(Self {
+                id: miss.id,
+                part: None,
+                judgement: None,
+                offset_us: None,
+                time_us: micros(miss.time_stamp),
+                place: miss.worker_property,
+            }),
+            _ => None,
+        }
+    }
+
+    /// 还原为判定结果，用于计分
+    pub fn to_event(&self) -> RuntimeEvent<RtV> {
+        let time_stamp = ChartTime::microseconds(self.time_us);
+        match (self.part, self.judgement) {
+            (Some(part), Some(judgement)) => RuntimeEvent::Some(RtV {
+                is_blank: false,
+                id: self.id,
+                judgement,
+                part,
+                offset: ChartTime::microseconds(self.offset_us.unwrap_or_default()),
+                place: self.place,
+                time_stamp,
+            }),
+            _ => RuntimeEvent::Missed(MissRecord {
+                id: self.id,
+                worker_property: self.place,
+                time_stamp,
+            }),
+        }
+    }
+
+    // 不同轨道的判定线程并行执行，按时间、编号与部位排序后结果才确定
+    fn sort_key(&self) -> (i64, usize, bool) {
+        (self.time_us, self.id, self.part != Some(NotePart::HoldHead))
+    }
+}
+
+// 按回放中的时间将输入送入判定线程，用于在渲染器中观看回放
+pub async fn start_replay_feed(
+    sndr: mpsc::Sender<Event>,
+    replay: &Replay,
+    clock: SharedClock,
+    held: Arc<HeldLanes>,
+) -> JoinHandle<()> {
+    let frames = replay.frames.clone();
+    tokio::spawn(async move {
+        for event in frames.into_iter().map(ReplayFrame::to_event) {
+            let wait = event.time_stamp - clock.now();
+            if let Ok(wait) = wait.to_std() {
+                tokio::time::sleep(wait).await;
+            }
+            held.track(&event);
+            if sndr.send(event).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{HoldState, ms};
+
+    fn widgets(profile: &Arc<JudgementProfile>) -> Vec<Widget> {
+        (0..4)
+            .map(|id| {
+                let kind = match id {
+                    3 => WidgetKind::Hold {
+                        time_end: ms(5000),
+                        state: HoldState::Waiting,
+                    },
+                    _ => WidgetKind::Tap,
+                };
+                Widget::at(
+                    id,
+                    1000 * (id as i64 + 1),
+                    WkrType::Lane(id as u8 % 2),
+                    kind,
+                    profile,
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn replay_reproduces_judgements() {
+        let profile = Arc::new(JudgementProfile::osu_mania(5.));
+        let mut replay = Replay::new(chart_hash(&"chart"), (*profile).clone());
+        for (ms, event_ppty) in [
+            (995, EventType::Press(0)),
+            (1030, EventType::Release(0)),
+            // 第二个音符没有按下
+            (2900, EventType::Press(0)),
+            (2950, EventType::Release(0)),
+            (4010, EventType::Press(1)),
+            (4500, EventType::All),
+            (5050, EventType::Release(1)),
+        ] {
+            replay.record(&Event {
+                time_stamp: ChartTime::milliseconds(ms),
+                event_ppty,
+            });
+        }
+        assert_eq!(replay.frames.len(), 6);
+        assert_eq!(
+            Replay::from_binary(&replay.to_binary().unwrap()).unwrap(),
+            replay
+        );
+        assert_eq!(
+            Replay::from_json(&replay.to_json().unwrap()).unwrap(),
+            replay
+        );
+
+        let first = replay.play(widgets(&profile)).await;
+        for result in &first {
+            assert_eq!(NoteResult::from_event(&result.to_event()), Some(*result));
+        }
+        let summary: Vec<_> = first
+            .iter()
+            .map(|result| {
+                (
+                    result.id,
+                    result.judgement,
+                    result.offset_us,
+                    result.time_us,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (0, Some(Judgement::CriticalPerfect), Some(-5_000), 995_000),
+                (1, None, None, 2_000_000),
+                (2, Some(Judgement::Good), Some(-100_000), 2_900_000),
+                (3, Some(Judgement::CriticalPerfect), Some(10_000), 4_010_000),
+                (3, Some(Judgement::Great), Some(50_000), 5_050_000),
+            ]
+        );
+        for _ in 0..5 {
+            assert_eq!(replay.play(widgets(&profile)).await, first);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn dense_replay_keeps_workers_in_step() {
+        let profile = Arc::new(JudgementProfile::osu_mania(5.));
+        let mut frames = vec![
+            (995, EventType::Press(0)),
+            (1030, EventType::Release(0)),
+            (4010, EventType::Press(1)),
+            (5050, EventType::Release(1)),
+        ];
+        // 没有音符的轨道上远超广播容量的输入
+        for ms in 0..6000 {
+            frames.push((ms, EventType::Press(7)));
+            frames.push((ms, EventType::Release(7)));
+        }
+        frames.sort_by_key(|&(ms, _)| ms);
+        let mut dense = Replay::new(String::new(), (*profile).clone());
+        let mut sparse = dense.clone();
+        for (ms, event_ppty) in frames {
+            let event = Event {
+                time_stamp: ChartTime::milliseconds(ms),
+                event_ppty,
+            };
+            dense.record(&event);
+            if event_ppty != EventType::Press(7) && event_ppty != EventType::Release(7) {
+                sparse.record(&event);
+            }
+        }
+
+        let expected = sparse.play(widgets(&profile)).await;
+        assert_eq!(expected.len(), 5);
+        assert_eq!(dense.play(widgets(&profile)).await, expected);
+    }
+}
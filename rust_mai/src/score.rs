@@ -0,0 +1,508 @@
+use crate::types::{Judgement, NotePart, RtV, Widget, WidgetKind, WkrType};
+
+use general_time_event_driven::types::RuntimeEvent;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tokio::sync::mpsc;
+
+/// 计分时区分的音符种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NoteKind {
+    Tap,
+    Hold,
+    Slide,
+    Touch,
+}
+
+/// 计分所需的音符信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NoteInfo {
+    pub kind: NoteKind,
+    pub is_break: bool,
+}
+
+impl NoteInfo {
+    /// 音符包含的判定对象数，长条的头部与尾部各算一个
+    pub fn objects(&self) -> u32 {
+        match self.kind {
+            NoteKind::Hold => 2,
+            _ => 1,
+        }
+    }
+
+    // 音符被整个错过时各判定对象对应的部位
+    fn parts(&self) -> &'static [NotePart] {
+        match self.kind {
+            NoteKind::Tap => &[NotePart::Tap],
+            NoteKind::Hold => &[NotePart::HoldHead, NotePart::HoldTail],
+            NoteKind::Slide => &[NotePart::Slide],
+            NoteKind::Touch => &[NotePart::Touch],
+        }
+    }
+}
+
+impl From<&Widget> for NoteInfo {
+    fn from(widget: &Widget) -> Self {
+        let kind = match (&widget.kind, widget.wkr_ppty) {
+            (WidgetKind::Hold { .. }, _) => NoteKind::Hold,
+            (WidgetKind::Slide { .. }, _) => NoteKind::Slide,
+            (WidgetKind::Tap, WkrType::Sensor(_)) => NoteKind::Touch,
+            (WidgetKind::Tap, _) => NoteKind::Tap,
+        };
+        Self {
+            kind,
+            is_break: widget.is_break,
+        }
+    }
+}
+
+/// 一个判定对象的结果，`judgement` 为 `None` 表示 Miss
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hit {
+    pub note: NoteInfo,
+    pub part: NotePart,
+    pub judgement: Option<Judgement>,
+}
+
+/// 与计分规则无关的统计
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tally {
+    pub combo: u32,
+    pub max_combo: u32,
+    pub counts: BTreeMap<Judgement, u32>,
+    pub misses: u32,
+    /// 已判定的对象数
+    pub judged: u32,
+    /// 谱面中的对象总数
+    pub total: u32,
+}
+
+impl Tally {
+    fn record(&mut self, hit: &Hit) {
+        self.judged += 1;
+        match hit.judgement {
+            Some(judgement) => {
+                *self.counts.entry(judgement).or_default() += 1;
+                self.combo += 1;
+                self.max_combo = self.max_combo.max(self.combo);
+            }
+            None => {
+                self.misses += 1;
+                self.combo = 0;
+            }
+        }
+    }
+
+    pub fn count(&self, judgement: Judgement) -> u32 {
+        self.counts.get(&judgement).copied().unwrap_or(0)
+    }
+}
+
+/// 计分规则
+///
+/// `prepare` 在游玩开始前以谱面的全部音符调用一次，之后每个判定对象调用一次 `record`
+pub trait ScoreRule: Send {
+    fn name(&self) -> &'static str;
+    fn prepare(&mut self, notes: &[NoteInfo]);
+    /// 记录判定对象，调用时 `tally` 已包含该对象
+    fn record(&mut self, hit: &Hit, tally: &Tally);
+    /// 当前分数
+    fn score(&self, tally: &Tally) -> f64;
+    /// 当前准确率（百分比）
+    fn accuracy(&self, tally: &Tally) -> f64;
+    /// DX 分数及其上限，仅 maimai 规则提供
+    fn dx_score(&self) -> Option<(u32, u32)> {
+        None
+    }
+}
+
+/// 按名称选择计分规则：`v1`、`v2` 或 `maimai`
+pub fn rule_by_name(name: &str) -> Option<Box<dyn ScoreRule>> {
+    match name.to_ascii_lowercase().as_str() {
+        "v1" | "scorev1" => Some(Box::new(ScoreV1::default())),
+        "v2" | "scorev2" => Some(Box::new(ScoreV2::default())),
+        "maimai" | "dx" => Some(Box::new(MaimaiDx::default())),
+        _ => None,
+    }
+}
+
+// osu!mania 的准确率，`max` 为 MAX 的权重（ScoreV1 为300，ScoreV2 为305）
+fn osu_accuracy(tally: &Tally, max: f64) -> f64 {
+    if tally.judged == 0 {
+        return 100.;
+    }
+    let sum = tally.count(Judgement::CriticalPerfect) as f64 * max
+        + tally.count(Judgement::Perfect) as f64 * 300.
+        + tally.count(Judgement::Great) as f64 * 200.
+        + tally.count(Judgement::Good) as f64 * 100.
+        + tally.count(Judgement::Meh) as f64 * 50.;
+    sum / (tally.judged as f64 * max) * 100.
+}
+
+/// osu!mania ScoreV1，满分1,000,000
+///
+/// 每个对象的分数分为基础分与奖励分两半，奖励分随 MAX/300 累积、随低判定扣减
+#[derive(Debug, Clone)]
+pub struct ScoreV1 {
+    per_object: f64,
+    bonus: f64,
+    score: f64,
+}
+
+impl Default for ScoreV1 {
+    fn default() -> Self {
+        Self {
+            per_object: 0.,
+            bonus: 100.,
+            score: 0.,
+        }
+    }
+}
+
+impl ScoreRule for ScoreV1 {
+    fn name(&self) -> &'static str {
+        "osu!mania ScoreV1"
+    }
+
+    fn prepare(&mut self, notes: &[NoteInfo]) {
+        let total: u32 = notes.iter().map(NoteInfo::objects).sum();
+        self.per_object = 500_000. / total.max(1) as f64;
+    }
+
+    fn record(&mut self, hit: &Hit, _: &Tally) {
+        // (基础分, 奖励分, 奖励增加, 奖励扣减)
+        let (value, bonus_value, gain, punish) = match hit.judgement {
+            Some(Judgement::CriticalPerfect) => (320., 32., 2., 0.),
+            Some(Judgement::Perfect) => (300., 32., 1., 0.),
+            Some(Judgement::Great) => (200., 16., 0., 8.),
+            Some(Judgement::Good) => (100., 8., 0., 24.),
+            Some(Judgement::Meh) => (50., 4., 0., 44.),
+            None => (0., 0., 0., 100.),
+        };
+        self.bonus = (self.bonus + gain - punish).clamp(0., 100.);
+        self.score += self.per_object * value / 320.;
+        self.score += self.per_object * bonus_value * self.bonus.sqrt() / 320.;
+    }
+
+    fn score(&self, _: &Tally) -> f64 {
+        self.score.round()
+    }
+
+    fn accuracy(&self, tally: &Tally) -> f64 {
+        osu_accuracy(tally, 300.)
+    }
+}
+
+/// osu!mania ScoreV2，满分1,000,000
+///
+/// 85% 来自准确率（MAX 计305），15% 来自连击：每个对象按击打时的连击数计权
+#[derive(Debug, Clone, Default)]
+pub struct ScoreV2 {
+    total: u32,
+    value: f64,
+    combo: f64,
+}
+
+impl ScoreRule for ScoreV2 {
+    fn name(&self) -> &'static str {
+        "osu!mania ScoreV2"
+    }
+
+    fn prepare(&mut self, notes: &[NoteInfo]) {
+        self.total = notes.iter().map(NoteInfo::objects).sum();
+    }
+
+    fn record(&mut self, hit: &Hit, tally: &Tally) {
+        self.value += match hit.judgement {
+            Some(Judgement::CriticalPerfect) => 305.,
+            Some(Judgement::Perfect) => 300.,
+            Some(Judgement::Great) => 200.,
+            Some(Judgement::Good) => 100.,
+            Some(Judgement::Meh) => 50.,
+            None => 0.,
+        };
+        self.combo += tally.combo as f64;
+    }
+
+    fn score(&self, _: &Tally) -> f64 {
+        let total = self.total.max(1) as f64;
+        // 全连时连击权重之和为 1 + 2 + ... + total
+        let max_combo = total * (total + 1.) / 2.;
+        (850_000. * self.value / (305. * total) + 150_000. * self.combo / max_combo).round()
+    }
+
+    fn accuracy(&self, tally: &Tally) -> f64 {
+        osu_accuracy(tally, 305.)
+    }
+}
+
+/// maimai DX 的达成率与 DX 分数
+///
+/// Tap/Touch 与长条的头尾各500，Slide 1500，Break 对象2500；
+/// Break 另有合计 1% 的额外奖励。DX 分数按 CriticalPerfect/Perfect/Great 计 3/2/1
+#[derive(Debug, Clone, Default)]
+pub struct MaimaiDx {
+    max_base: f64,
+    base: f64,
+    breaks: u32,
+    bonus: f64,
+    dx: u32,
+    max_dx: u32,
+}
+
+impl MaimaiDx {
+    fn weight(note: &NoteInfo, part: NotePart) -> f64 {
+        match (note.is_break, part) {
+            (true, _) => 2500.,
+            (false, NotePart::Slide) => 1500.,
+            _ => 500.,
+        }
+    }
+}
+
+impl ScoreRule for MaimaiDx {
+    fn name(&self) -> &'static str {
+        "maimai DX"
+    }
+
+    fn prepare(&mut self, notes: &[NoteInfo]) {
+        for note in notes {
+            for &part in note.parts() {
+                self.max_base += Self::weight(note, part);
+                self.max_dx += 3;
+                self.breaks += note.is_break as u32;
+            }
+        }
+    }
+
+    fn record(&mut self, hit: &Hit, _: &Tally) {
+        let weight = Self::weight(&hit.note, hit.part);
+        // 没有 Meh 的判定配置下不会出现 Meh，按 Good 处理
+        let (ratio, break_ratio, bonus, dx) = match hit.judgement {
+            Some(Judgement::CriticalPerfect) => (1., 1., 1., 3),
+            Some(Judgement::Perfect) => (1., 1., 0.75, 2),
+            Some(Judgement::Great) => (0.8, 0.8, 0.4, 1),
+            Some(Judgement::Good | Judgement::Meh) => (0.5, 0.4, 0.3, 0),
+            None => (0., 0., 0., 0),
+        };
+        match hit.note.is_break {
+            true => {
+                self.base += weight * break_ratio;
+                self.bonus += bonus;
+            }
+            false => self.base += weight * ratio,
+        }
+        self.dx += dx;
+    }
+
+    fn score(&self, tally: &Tally) -> f64 {
+        self.accuracy(tally)
+    }
+
+    /// 达成率（百分比），理论值为101%
+    fn accuracy(&self, _: &Tally) -> f64 {
+        let base = self.base / self.max_base.max(1.) * 100.;
+        let bonus = match self.breaks {
+            0 => 0.,
+            breaks => self.bonus / breaks as f64,
+        };
+        base + bonus
+    }
+
+    fn dx_score(&self) -> Option<(u32, u32)> {
+        Some((self.dx, self.max_dx))
+    }
+}
+
+/// 一局的成绩
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScoreSummary {
+    pub rule: String,
+    pub score: f64,
+    pub accuracy: f64,
+    pub combo: u32,
+    pub max_combo: u32,
+    pub counts: BTreeMap<Judgement, u32>,
+    pub misses: u32,
+    pub dx_score: Option<(u32, u32)>,
+}
+
+/// 将判定结果流转换为分数
+pub struct Scorer {
+    notes: HashMap<usize, NoteInfo>,
+    // 头部已判定的长条
+    held: HashSet<usize>,
+    tally: Tally,
+    rule: Box<dyn ScoreRule>,
+}
+
+impl Scorer {
+    pub fn new(
+        mut rule: Box<dyn ScoreRule>,
+        notes: impl IntoIterator<Item = (usize, NoteInfo)>,
+    ) -> Self {
+        let notes: HashMap<_, _> = notes.into_iter().collect();
+        let infos: Vec<_> = notes.values().copied().collect();
+        rule.prepare(&infos);
+        Self {
+            tally: Tally {
+                total: infos.iter().map(NoteInfo::objects).sum(),
+                ..Tally::default()
+            },
+            notes,
+            held: HashSet::new(),
+            rule,
+        }
+    }
+
+    /// 按谱面的判定组件建立
+    pub fn for_widgets<'a>(
+        rule: Box<dyn ScoreRule>,
+        widgets: impl IntoIterator<Item = &'a Widget>,
+    ) -> Self {
+        Self::new(rule, widgets.into_iter().map(|w| (w.id, NoteInfo::from(w))))
+    }
+
+    /// 处理一个判定结果，空返回值与未知的组件被忽略
+    pub fn apply(&mut self, event: &RuntimeEvent<RtV>) {
+        match event {
+            RuntimeEvent::Some(rtv) if !rtv.is_blank => {
+                let Some(&note) = self.notes.get(&rtv.id) else {
+                    return;
+                };
+                if rtv.part == NotePart::HoldHead {
+                    self.held.insert(rtv.id);
+                }
+                self.record(Hit {
+                    note,
+                    part: rtv.part,
+                    judgement: Some(rtv.judgement),
+                });
+            }
+            RuntimeEvent::Missed(miss) => {
+                let Some(&note) = self.notes.get(&miss.id) else {
+                    return;
+                };
+                // 头部已判定的长条只错过尾部，否则整个音符的对象都算 Miss
+                let parts = match self.held.remove(&miss.id) {
+                    true => &[NotePart::HoldTail][..],
+                    false => note.parts(),
+                };
+                for &part in parts {
+                    self.record(Hit {
+                        note,
+                        part,
+                        judgement: None,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn record(&mut self, hit: Hit) {
+        self.tally.record(&hit);
+        self.rule.record(&hit, &self.tally);
+    }
+
+    pub fn tally(&self) -> &Tally {
+        &self.tally
+    }
+
+    /// 所有对象均已判定
+    pub fn is_finished(&self) -> bool {
+        self.tally.judged >= self.tally.total
+    }
+
+    pub fn summary(&self) -> ScoreSummary {
+        ScoreSummary {
+            rule: self.rule.name().to_string(),
+            score: self.rule.score(&self.tally),
+            accuracy: self.rule.accuracy(&self.tally),
+            combo: self.tally.combo,
+            max_combo: self.tally.max_combo,
+            counts: self.tally.counts.clone(),
+            misses: self.tally.misses,
+            dx_score: self.rule.dx_score(),
+        }
+    }
+
+    /// 消费判定结果流，直到所有对象判定完毕或流关闭
+    pub async fn run(mut self, mut rcvr: mpsc::Receiver<RuntimeEvent<RtV>>) -> ScoreSummary {
+        while !self.is_finished()
+            && let Some(event) = rcvr.recv().await
+        {
+            self.apply(&event);
+        }
+        self.summary()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ChartTime, Miss};
+    use general_time_event_driven::types::MissRecord;
+
+    fn hit(id: usize, part: NotePart, judgement: Judgement) -> RuntimeEvent<RtV> {
+        RuntimeEvent::Some(RtV {
+            is_blank: false,
+            judgement,
+            part,
+            ..RtV::blank(id)
+        })
+    }
+
+    fn miss(id: usize) -> RuntimeEvent<RtV> {
+        let record: Miss = MissRecord {
+            id,
+            worker_property: WkrType::Lane(0),
+            time_stamp: ChartTime::zero(),
+        };
+        RuntimeEvent::Missed(record)
+    }
+
+    fn note(kind: NoteKind, is_break: bool) -> NoteInfo {
+        NoteInfo { kind, is_break }
+    }
+
+    #[test]
+    fn score_v1_all_max_and_missed_hold() {
+        let notes = [
+            (0, note(NoteKind::Tap, false)),
+            (1, note(NoteKind::Hold, false)),
+        ];
+        let mut scorer = Scorer::new(rule_by_name("v1").unwrap(), notes);
+        scorer.apply(&hit(0, NotePart::Tap, Judgement::CriticalPerfect));
+        scorer.apply(&hit(1, NotePart::HoldHead, Judgement::CriticalPerfect));
+        scorer.apply(&hit(1, NotePart::HoldTail, Judgement::CriticalPerfect));
+        assert!(scorer.is_finished());
+        let summary = scorer.summary();
+        assert_eq!(summary.score, 1_000_000.);
+        assert_eq!(summary.accuracy, 100.);
+        assert_eq!(summary.max_combo, 3);
+
+        // 未按下的长条头尾都算 Miss
+        let mut scorer = Scorer::new(rule_by_name("v2").unwrap(), notes);
+        scorer.apply(&hit(0, NotePart::Tap, Judgement::Great));
+        scorer.apply(&miss(1));
+        assert!(scorer.is_finished());
+        assert_eq!(scorer.tally().misses, 2);
+        assert_eq!(scorer.tally().combo, 0);
+    }
+
+    #[test]
+    fn maimai_break_bonus_and_dx_score() {
+        let notes = [
+            (0, note(NoteKind::Tap, false)),
+            (1, note(NoteKind::Tap, true)),
+        ];
+        let mut scorer = Scorer::new(rule_by_name("maimai").unwrap(), notes);
+        scorer.apply(&RuntimeEvent::Some(RtV::blank(0)));
+        scorer.apply(&hit(0, NotePart::Tap, Judgement::CriticalPerfect));
+        scorer.apply(&hit(1, NotePart::Tap, Judgement::Perfect));
+        let summary = scorer.summary();
+        assert_eq!(summary.accuracy, 100.75);
+        assert_eq!(summary.dx_score, Some((5, 6)));
+    }
+}
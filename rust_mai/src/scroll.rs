@@ -0,0 +1,167 @@
+use crate::beatmap::TimingPoint;
+
+// 一段流速恒定的区间
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Segment {
+    time: f64,
+    position: f64,
+    velocity: f64,
+}
+
+/// 流速映射
+///
+/// 对流速随时间积分，得到每个时刻的卷轴位置（单位与毫秒相同，流速为1时位置等于时间）
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScrollMap {
+    segments: Vec<Segment>,
+}
+
+impl ScrollMap {
+    /// 由时间点构建流速映射
+    ///
+    /// 非继承时间点设置BPM并将流速倍率重置为1，继承时间点设置流速倍率。
+    /// 实际流速 = 流速倍率 × BPM / 主BPM，主BPM为 `end_time` 前持续时间最长的BPM
+    pub fn new(timing_points: &[TimingPoint], end_time: f64) -> Self {
+        let mut points: Vec<&TimingPoint> = timing_points.iter().collect();
+        // 同一时刻先应用非继承时间点
+        points.sort_by(|a, b| {
+            a.time
+                .total_cmp(&b.time)
+                .then(b.uninherited.cmp(&a.uninherited))
+        });
+
+        let base_bpm = Self::main_bpm(&points, end_time);
+
+        // 先求出每段的起始时间与流速，同一时刻以最后一个时间点为准
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut bpm = base_bpm;
+        for point in points {
+            let sv = if let Some(point_bpm) = point.bpm() {
+                bpm = point_bpm;
+                1.
+            } else if let Some(multiplier) = point.sv_multiplier() {
+                multiplier.clamp(0.01, 10.)
+            } else {
+                continue;
+            };
+            let velocity = sv * bpm / base_bpm;
+            match segments.last_mut() {
+                Some(last) if last.time == point.time => last.velocity = velocity,
+                _ => segments.push(Segment {
+                    time: point.time,
+                    position: 0.,
+                    velocity,
+                }),
+            }
+        }
+
+        // 再对流速积分得到每段起点的位置，第一段之前沿用第一段的流速
+        for i in 0..segments.len() {
+            segments[i].position = match i {
+                0 => segments[0].time * segments[0].velocity,
+                _ => {
+                    let last = segments[i - 1];
+                    last.position + (segments[i].time - last.time) * last.velocity
+                }
+            };
+        }
+
+        if segments.is_empty() {
+            segments.push(Segment {
+                time: 0.,
+                position: 0.,
+                velocity: 1.,
+            });
+        }
+
+        Self { segments }
+    }
+
+    // 持续时间最长的BPM
+    fn main_bpm(points: &[&TimingPoint], end_time: f64) -> f64 {
+        let uninherited: Vec<&&TimingPoint> = points.iter().filter(|p| p.bpm().is_some()).collect();
+        let mut durations: Vec<(f64, f64)> = Vec::new();
+        for (i, point) in uninherited.iter().enumerate() {
+            let until = uninherited
+                .get(i + 1)
+                .map_or(end_time.max(point.time), |next| next.time);
+            let bpm = point.bpm().unwrap();
+            match durations.iter_mut().find(|(b, _)| (*b - bpm).abs() < 1e-6) {
+                Some((_, duration)) => *duration += until - point.time,
+                None => durations.push((bpm, until - point.time)),
+            }
+        }
+        durations
+            .into_iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(1., |(bpm, _)| bpm)
+    }
+
+    // 时间所在区间
+    fn segment_at(&self, time: f64) -> &Segment {
+        let index = self.segments.partition_point(|s| s.time <= time);
+        &self.segments[index.saturating_sub(1)]
+    }
+
+    /// 某一时刻（毫秒）的卷轴位置
+    pub fn position_at(&self, time: f64) -> f64 {
+        let segment = self.segment_at(time);
+        segment.position + (time - segment.time) * segment.velocity
+    }
+
+    /// 到达某一卷轴位置的时刻（毫秒）
+    pub fn time_at(&self, position: f64) -> f64 {
+        let index = self.segments.partition_point(|s| s.position <= position);
+        let segment = &self.segments[index.saturating_sub(1)];
+        segment.time + (position - segment.position) / segment.velocity
+    }
+
+    /// 某一时刻的流速
+    pub fn velocity_at(&self, time: f64) -> f64 {
+        self.segment_at(time).velocity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(time: f64, beat_length: f64, uninherited: bool) -> TimingPoint {
+        TimingPoint {
+            time,
+            beat_length,
+            meter: 4,
+            sample_set: 0,
+            sample_index: 0,
+            volume: 100,
+            uninherited,
+            effects: 0,
+        }
+    }
+
+    #[test]
+    fn constant_bpm_is_identity() {
+        let map = ScrollMap::new(&[point(0., 500., true)], 10_000.);
+        assert_eq!(map.position_at(1234.), 1234.);
+        assert_eq!(map.time_at(1234.), 1234.);
+    }
+
+    #[test]
+    fn sv_and_bpm_changes_integrate() {
+        let map = ScrollMap::new(
+            &[
+                point(0., 500., true),
+                // 1000ms 起两倍流速
+                point(1000., -50., false),
+                // 2000ms 起BPM减半，流速倍率重置
+                point(2000., 1000., true),
+            ],
+            3000.,
+        );
+        assert_eq!(map.velocity_at(500.), 1.);
+        assert_eq!(map.position_at(2000.), 3000.);
+        assert_eq!(map.velocity_at(2500.), 0.5);
+        assert_eq!(map.position_at(4000.), 4000.);
+        assert_eq!(map.time_at(3000.), 2000.);
+    }
+}
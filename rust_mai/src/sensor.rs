@@ -0,0 +1,185 @@
+use serde::{Deserialize, Serialize};
+
+use std::f64::consts::TAU;
+use std::fmt;
+
+/// maimai 触摸区域
+///
+/// 编号从1到8，与按键相同沿顺时针排列，A1/B1/E1/D1 位于正上方附近
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Zone {
+    /// 外圈，与按键对齐
+    A(u8),
+    /// 内圈，与按键对齐
+    B(u8),
+    /// 中心
+    C,
+    /// 外圈边缘，位于两个按键之间
+    D(u8),
+    /// 内外圈之间，位于两个按键之间
+    E(u8),
+}
+
+impl Zone {
+    /// 全部33个区域
+    pub fn all() -> impl Iterator<Item = Zone> {
+        (1..=8)
+            .flat_map(|i| [Zone::A(i), Zone::B(i), Zone::D(i), Zone::E(i)])
+            .chain(std::iter::once(Zone::C))
+    }
+
+    /// 解析 `A1`、`C`、`C1` 等写法
+    pub fn parse(s: &str) -> Option<Zone> {
+        let mut chars = s.chars();
+        let letter = chars.next()?;
+        let rest = chars.as_str();
+        if letter == 'C' {
+            return matches!(rest, "" | "1" | "2").then_some(Zone::C);
+        }
+        let index: u8 = rest.parse().ok().filter(|i| (1..=8).contains(i))?;
+        match letter {
+            'A' => Some(Zone::A(index)),
+            'B' => Some(Zone::B(index)),
+            'D' => Some(Zone::D(index)),
+            'E' => Some(Zone::E(index)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Zone::A(i) => write!(f, "A{i}"),
+            Zone::B(i) => write!(f, "B{i}"),
+            Zone::C => write!(f, "C"),
+            Zone::D(i) => write!(f, "D{i}"),
+            Zone::E(i) => write!(f, "E{i}"),
+        }
+    }
+}
+
+// 区域边界（以判定圆半径为1）
+const C_RADIUS: f64 = 0.25;
+const B_OUTER_RADIUS: f64 = 0.6;
+const D_INNER_RADIUS: f64 = 0.82;
+const D_HALF_ANGLE: f64 = 9.;
+const E_INNER_RADIUS: f64 = 0.45;
+const E_OUTER_RADIUS: f64 = 0.75;
+const E_HALF_ANGLE: f64 = 12.;
+
+/// 按键所在的半径
+pub const BUTTON_RADIUS: f64 = 0.9;
+
+/// 按键中心的角度（度，自正上方顺时针）
+pub fn button_angle(button: u8) -> f64 {
+    22.5 + 45. * (button as f64 - 1.)
+}
+
+/// 极坐标转直角坐标（y轴向上）
+pub fn polar(radius: f64, angle: f64) -> (f64, f64) {
+    let rad = angle.to_radians();
+    (radius * rad.sin(), radius * rad.cos())
+}
+
+/// 按键中心的坐标
+pub fn button_position(button: u8) -> (f64, f64) {
+    polar(BUTTON_RADIUS, button_angle(button))
+}
+
+// 直角坐标转极坐标，角度在 [0, 360)
+fn to_polar(x: f64, y: f64) -> (f64, f64) {
+    let angle = x.atan2(y).rem_euclid(TAU).to_degrees();
+    ((x * x + y * y).sqrt(), angle)
+}
+
+// 与按键对齐的扇区编号
+fn sector(angle: f64) -> u8 {
+    (angle / 45.) as u8 % 8 + 1
+}
+
+// 位于两个按键之间的扇区编号，以及到扇区中心线的角距离
+fn gap_sector(angle: f64) -> (u8, f64) {
+    let shifted = (angle + 22.5).rem_euclid(360.);
+    let index = (shifted / 45.) as u8 % 8;
+    (index + 1, (shifted - index as f64 * 45. - 22.5).abs())
+}
+
+/// 坐标所在的触摸区域
+///
+/// 坐标以判定圆圆心为原点、半径为1，y轴向上；圆外返回 `None`
+pub fn zone_at(x: f64, y: f64) -> Option<Zone> {
+    let (radius, angle) = to_polar(x, y);
+    if radius > 1. {
+        return None;
+    }
+    if radius < C_RADIUS {
+        return Some(Zone::C);
+    }
+    let (gap, gap_distance) = gap_sector(angle);
+    if radius >= D_INNER_RADIUS && gap_distance < D_HALF_ANGLE {
+        return Some(Zone::D(gap));
+    }
+    if (E_INNER_RADIUS..E_OUTER_RADIUS).contains(&radius) && gap_distance < E_HALF_ANGLE {
+        return Some(Zone::E(gap));
+    }
+    Some(ring_zone(radius, angle))
+}
+
+// 只区分 A/B/C 的区域划分，用于星星轨迹
+fn ring_zone(radius: f64, angle: f64) -> Zone {
+    if radius < C_RADIUS {
+        Zone::C
+    } else if radius < B_OUTER_RADIUS {
+        Zone::B(sector(angle))
+    } else {
+        Zone::A(sector(angle))
+    }
+}
+
+/// 将折线依次经过的 A/B/C 区域按顺序列出
+pub fn zones_along(points: &[(f64, f64)]) -> Vec<Zone> {
+    const STEPS: usize = 64;
+    let mut zones: Vec<Zone> = Vec::new();
+    for pair in points.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        for step in 0..=STEPS {
+            let t = step as f64 / STEPS as f64;
+            let (radius, angle) = to_polar(x0 + (x1 - x0) * t, y0 + (y1 - y0) * t);
+            let zone = ring_zone(radius, angle);
+            if zones.last() != Some(&zone) {
+                zones.push(zone);
+            }
+        }
+    }
+    zones
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zone_layout() {
+        let (x, y) = button_position(1);
+        assert_eq!(zone_at(x, y), Some(Zone::A(1)));
+        let (x, y) = polar(0.4, button_angle(5));
+        assert_eq!(zone_at(x, y), Some(Zone::B(5)));
+        assert_eq!(zone_at(0.05, -0.05), Some(Zone::C));
+        let (x, y) = polar(0.95, 0.);
+        assert_eq!(zone_at(x, y), Some(Zone::D(1)));
+        let (x, y) = polar(0.6, 90.);
+        assert_eq!(zone_at(x, y), Some(Zone::E(3)));
+        assert_eq!(zone_at(1., 1.), None);
+        assert_eq!(Zone::all().count(), 33);
+    }
+
+    #[test]
+    fn parse_zone() {
+        assert_eq!(Zone::parse("A1"), Some(Zone::A(1)));
+        assert_eq!(Zone::parse("C2"), Some(Zone::C));
+        assert_eq!(Zone::parse("E8"), Some(Zone::E(8)));
+        assert_eq!(Zone::parse("B9"), None);
+        assert_eq!(Zone::A(3).to_string(), "A3");
+    }
+}
@@ -0,0 +1,95 @@
+use crate::types::ChartTime;
+
+use std::time::Instant;
+
+/// 一次游玩
+///
+/// 谱面中的时间均为相对歌曲开头的偏移，只有开始游玩时才与时钟对应。
+/// 重新开始、跳转或回放时只需创建新的 `PlaySession`，无需重新解析谱面
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaySession {
+    // 谱面时间 `start` 对应的时刻；换算在谱面时间上进行，不会因 `Instant` 下溢而出错
+    anchor: Instant,
+    start: ChartTime,
+}
+
+impl PlaySession {
+    /// 以当前时刻为谱面中的 `start` 开始游玩，`start` 为负表示歌曲开始前的等待时间
+    pub fn start_at(start: ChartTime) -> Self {
+        Self::anchored(Instant::now(), start)
+    }
+
+    /// 以 `instant` 时刻为谱面中的 `start`
+    pub fn anchored(instant: Instant, start: ChartTime) -> Self {
+        Self {
+            anchor: instant,
+            start,
+        }
+    }
+
+    /// 当前的谱面时间
+    pub fn now(&self) -> ChartTime {
+        self.chart_time_at(Instant::now())
+    }
+
+    /// 某一时刻对应的谱面时间
+    pub fn chart_time_at(&self, instant: Instant) -> ChartTime {
+        let since = |later: Instant, earlier: Instant| {
+            ChartTime::from_std(later - earlier).expect("游玩时长不会溢出")
+        };
+        if instant >= self.anchor {
+            self.start + since(instant, self.anchor)
+        } else {
+            self.start - since(self.anchor, instant)
+        }
+    }
+
+    /// 谱面时间对应的时刻，早于单调时钟起点时返回 `None`
+    pub fn instant_at(&self, chart_time: ChartTime) -> Option<Instant> {
+        let delta = chart_time - self.start;
+        let offset = delta.abs().to_std().expect("时长的绝对值非负");
+        if delta < ChartTime::zero() {
+            self.anchor.checked_sub(offset)
+        } else {
+            self.anchor.checked_add(offset)
+        }
+    }
+
+    /// 跳转到谱面中的 `chart_time` 继续游玩
+    pub fn seek(&mut self, chart_time: ChartTime) {
+        *self = Self::start_at(chart_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn chart_time_round_trip() {
+        let start = Instant::now();
+        let session = PlaySession::anchored(start, ChartTime::milliseconds(-3000));
+        assert_eq!(session.chart_time_at(start), ChartTime::milliseconds(-3000));
+        assert_eq!(
+            session.chart_time_at(start + Duration::from_millis(4500)),
+            ChartTime::milliseconds(1500)
+        );
+        assert_eq!(
+            session.instant_at(ChartTime::milliseconds(1500)),
+            Some(start + Duration::from_millis(4500))
+        );
+
+        // 跳转后谱面时间从新位置继续
+        let session = PlaySession::anchored(start, ChartTime::milliseconds(60_000));
+        assert_eq!(
+            session.chart_time_at(start + Duration::from_millis(1)),
+            ChartTime::milliseconds(60_001)
+        );
+        // 开始位置远大于开机时长时不计算谱面时间为0的时刻，不会使 `Instant` 下溢
+        let late = ChartTime::days(365 * 100);
+        let session = PlaySession::anchored(start, late);
+        assert_eq!(session.chart_time_at(start), late);
+        assert_eq!(session.instant_at(late), Some(start));
+    }
+}
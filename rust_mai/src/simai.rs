@@ -0,0 +1,898 @@
+use crate::judgement::JudgementProfile;
+use crate::parser::{ParseError, ParseErrorKind};
+use crate::sensor::{self, BUTTON_RADIUS, Zone};
+use crate::types::{ChartTime, HoldState, Widget, WidgetForDisplay, WidgetKind, WkrType};
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+/// maidata.txt 谱面文件
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Maidata {
+    pub title: String,
+    pub artist: String,
+    pub designer: String,
+    pub wholebpm: Option<f64>,
+    /// 第一个音符前的偏移（秒）
+    pub first: f64,
+    /// 各难度的等级，键为 `lv_N` 中的 N
+    pub levels: BTreeMap<u8, String>,
+    /// 各难度的谱面，键为 `inote_N` 中的 N
+    pub charts: BTreeMap<u8, SimaiChart>,
+    /// 未建模的字段
+    pub extra: Vec<(String, String)>,
+}
+
+/// 一个难度的谱面
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SimaiChart {
+    pub notes: Vec<SimaiNote>,
+}
+
+/// 音符
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimaiNote {
+    /// 判定时刻（毫秒，已包含 `&first`）
+    pub time: f64,
+    pub is_break: bool,
+    pub is_ex: bool,
+    pub kind: SimaiNoteKind,
+}
+
+/// 音符种类，时长均以毫秒为单位
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SimaiNoteKind {
+    Tap {
+        button: u8,
+        star: bool,
+    },
+    Hold {
+        button: u8,
+        duration: f64,
+    },
+    Touch {
+        zone: Zone,
+        firework: bool,
+    },
+    TouchHold {
+        zone: Zone,
+        duration: f64,
+        firework: bool,
+    },
+    /// 星星轨迹，从 `time + wait` 开始滑动，持续 `duration`
+    Slide {
+        start: u8,
+        segments: Vec<SlideSegment>,
+        wait: f64,
+        duration: f64,
+    },
+}
+
+/// 星星轨迹形状
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SlideShape {
+    /// `-`
+    Straight,
+    /// `^`
+    ShortArc,
+    /// `<`
+    Left,
+    /// `>`
+    Right,
+    /// `v`
+    Center,
+    /// `p`
+    P,
+    /// `q`
+    Q,
+    /// `s`
+    S,
+    /// `z`
+    Z,
+    /// `pp`
+    PP,
+    /// `qq`
+    QQ,
+    /// `V`，经由 `turn` 转折
+    V { turn: u8 },
+    /// `w`
+    Fan,
+}
+
+/// 轨迹中的一段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlideSegment {
+    pub shape: SlideShape,
+    pub end: u8,
+}
+
+// 圆弧上的采样点，角度为自正上方顺时针的度数
+fn arc(radius: f64, from: f64, to: f64, clockwise: bool) -> Vec<(f64, f64)> {
+    let mut sweep = if clockwise { to - from } else { from - to }.rem_euclid(360.);
+    if sweep == 0. {
+        sweep = 360.;
+    }
+    let steps = (sweep / 5.).ceil() as usize;
+    (0..=steps)
+        .map(|i| {
+            let offset = sweep * i as f64 / steps as f64;
+            sensor::polar(
+                radius,
+                if clockwise {
+                    from + offset
+                } else {
+                    from - offset
+                },
+            )
+        })
+        .collect()
+}
+
+impl SlideSegment {
+    /// 该段轨迹的折线（近似几何）
+    pub fn points(&self, from: u8) -> Vec<(f64, f64)> {
+        let (a, b) = (sensor::button_angle(from), sensor::button_angle(self.end));
+        let start = sensor::button_position(from);
+        let end = sensor::button_position(self.end);
+        // 经由内圈圆弧的轨迹
+        let around = |radius, clockwise| {
+            let mut points = vec![start];
+            points.extend(arc(radius, a, b, clockwise));
+            points.push(end);
+            points
+        };
+        match self.shape {
+            SlideShape::Straight | SlideShape::Fan => vec![start, end],
+            SlideShape::ShortArc => arc(BUTTON_RADIUS, a, b, (b - a).rem_euclid(360.) <= 180.),
+            // `>` 在上半圈为顺时针，在下半圈为逆时针，`<` 相反
+            SlideShape::Right => arc(BUTTON_RADIUS, a, b, !(3..=6).contains(&from)),
+            SlideShape::Left => arc(BUTTON_RADIUS, a, b, (3..=6).contains(&from)),
+            SlideShape::Center => vec![start, (0., 0.), end],
+            SlideShape::P => around(0.45, false),
+            SlideShape::Q => around(0.45, true),
+            SlideShape::PP => around(0.3, false),
+            SlideShape::QQ => around(0.3, true),
+            SlideShape::S => vec![
+                start,
+                sensor::polar(0.45, a - 90.),
+                sensor::polar(0.45, b - 90.),
+                end,
+            ],
+            SlideShape::Z => vec![
+                start,
+                sensor::polar(0.45, a + 90.),
+                sensor::polar(0.45, b + 90.),
+                end,
+            ],
+            SlideShape::V { turn } => vec![start, sensor::button_position(turn), end],
+        }
+    }
+}
+
+/// 星星轨迹依次经过的区域
+pub fn slide_path(start: u8, segments: &[SlideSegment]) -> Vec<Zone> {
+    let mut points = Vec::new();
+    let mut from = start;
+    for segment in segments {
+        points.extend(segment.points(from));
+        from = segment.end;
+    }
+    sensor::zones_along(&points)
+}
+
+// 带位置的字符
+#[derive(Debug, Clone, Copy)]
+struct Char {
+    c: char,
+    line: usize,
+    column: usize,
+}
+
+impl Char {
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            kind,
+        }
+    }
+}
+
+// 去掉空白与 `||` 注释，保留每个字符的位置
+fn chart_chars(text: &str, mut line: usize, mut column: usize) -> Vec<Char> {
+    let mut chars = Vec::new();
+    let mut in_comment = false;
+    let mut iter = text.chars().peekable();
+    while let Some(c) = iter.next() {
+        if c == '\n' {
+            line += 1;
+            column = 1;
+            in_comment = false;
+            continue;
+        }
+        if !in_comment && c == '|' && iter.peek() == Some(&'|') {
+            in_comment = true;
+        }
+        if !in_comment && !c.is_whitespace() {
+            chars.push(Char { c, line, column });
+        }
+        column += 1;
+    }
+    chars
+}
+
+fn text_of(chars: &[Char]) -> String {
+    chars.iter().map(|c| c.c).collect()
+}
+
+// 读取从 `start` 处开括号到 `close` 之间的内容，返回内容与闭括号之后的下标
+fn enclosed(chars: &[Char], start: usize, close: char) -> Result<(String, usize), ParseError> {
+    let end = chars[start..]
+        .iter()
+        .position(|c| c.c == close)
+        .ok_or_else(|| chars[start].error(ParseErrorKind::Unclosed(close)))?;
+    Ok((text_of(&chars[start + 1..start + end]), start + end + 1))
+}
+
+// `n:m` 表示 m 个 n 分音符
+fn beats(content: &str, bpm: f64) -> Option<f64> {
+    let (n, m) = content.split_once(':')?;
+    let (n, m): (f64, f64) = (n.parse().ok()?, m.parse().ok()?);
+    (n > 0.).then(|| 240. / bpm / n * m)
+}
+
+// 时长括号的内容，返回（等待时间, 时长），单位为秒
+//
+// 支持 `n:m`、`#秒`、`bpm#n:m`、`bpm#秒`、`等待##秒`、`等待##n:m`
+fn duration(content: &str, bpm: f64) -> Option<(Option<f64>, f64)> {
+    let length = |s: &str, bpm| match s.contains(':') {
+        true => beats(s, bpm),
+        false => s.parse().ok(),
+    };
+    if let Some((wait, rest)) = content.split_once("##") {
+        return Some((Some(wait.parse().ok()?), length(rest, bpm)?));
+    }
+    match content.split_once('#') {
+        Some(("", seconds)) => Some((None, seconds.parse().ok()?)),
+        Some((custom_bpm, rest)) => {
+            let custom_bpm: f64 = custom_bpm.parse().ok()?;
+            Some((Some(60. / custom_bpm), length(rest, custom_bpm)?))
+        }
+        None => Some((None, beats(content, bpm)?)),
+    }
+}
+
+// 单个音符的读取游标
+struct NoteCursor<'a> {
+    chars: &'a [Char],
+    pos: usize,
+    bpm: f64,
+}
+
+#[derive(Default)]
+struct Flags {
+    is_break: bool,
+    is_ex: bool,
+    star: bool,
+    no_star: bool,
+    firework: bool,
+}
+
+impl NoteCursor<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).map(|c| c.c)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).map(|c| c.c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let matched = self.peek() == Some(c);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn invalid(&self) -> ParseError {
+        let at = self.chars.get(self.pos).unwrap_or(&self.chars[0]);
+        at.error(ParseErrorKind::InvalidNote(text_of(self.chars)))
+    }
+
+    fn button(&mut self) -> Result<u8, ParseError> {
+        match self.peek().and_then(|c| c.to_digit(10)) {
+            Some(d @ 1..=8) => {
+                self.pos += 1;
+                Ok(d as u8)
+            }
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn flags(&mut self, flags: &mut Flags) {
+        while let Some(c) = self.peek() {
+            match c {
+                'b' => flags.is_break = true,
+                'x' => flags.is_ex = true,
+                '$' => flags.star = true,
+                '@' => {}
+                '?' | '!' => flags.no_star = true,
+                'f' => flags.firework = true,
+                _ => break,
+            }
+            self.pos += 1;
+        }
+    }
+
+    // 可选的时长括号
+    fn duration(&mut self) -> Result<Option<(Option<f64>, f64)>, ParseError> {
+        if self.peek() != Some('[') {
+            return Ok(None);
+        }
+        let (content, next) = enclosed(self.chars, self.pos, ']')?;
+        let result = duration(&content, self.bpm).ok_or_else(|| self.invalid())?;
+        self.pos = next;
+        Ok(Some(result))
+    }
+
+    fn shape(&mut self) -> Result<Option<SlideSegment>, ParseError> {
+        let shape = match (self.peek(), self.peek_at(1)) {
+            (Some('p'), Some('p')) => SlideShape::PP,
+            (Some('q'), Some('q')) => SlideShape::QQ,
+            (Some('-'), _) => SlideShape::Straight,
+            (Some('^'), _) => SlideShape::ShortArc,
+            (Some('<'), _) => SlideShape::Left,
+            (Some('>'), _) => SlideShape::Right,
+            (Some('v'), _) => SlideShape::Center,
+            (Some('p'), _) => SlideShape::P,
+            (Some('q'), _) => SlideShape::Q,
+            (Some('s'), _) => SlideShape::S,
+            (Some('z'), _) => SlideShape::Z,
+            (Some('w'), _) => SlideShape::Fan,
+            (Some('V'), _) => SlideShape::V { turn: 0 },
+            _ => return Ok(None),
+        };
+        self.pos += if matches!(shape, SlideShape::PP | SlideShape::QQ) {
+            2
+        } else {
+            1
+        };
+        let shape = match shape {
+            SlideShape::V { .. } => SlideShape::V {
+                turn: self.button()?,
+            },
+            shape => shape,
+        };
+        Ok(Some(SlideSegment {
+            shape,
+            end: self.button()?,
+        }))
+    }
+}
+
+// 解析一个音符（不含 `/`），结果追加到 `notes`
+fn parse_note(
+    chars: &[Char],
+    time: f64,
+    bpm: f64,
+    notes: &mut Vec<SimaiNote>,
+) -> Result<(), ParseError> {
+    let mut cursor = NoteCursor { chars, pos: 0, bpm };
+    let mut flags = Flags::default();
+    let mut push = |flags: &Flags, kind| {
+        notes.push(SimaiNote {
+            time,
+            is_break: flags.is_break,
+            is_ex: flags.is_ex,
+            kind,
+        })
+    };
+
+    match chars[0].c {
+        '1'..='8' => {
+            let button = cursor.button()?;
+            cursor.flags(&mut flags);
+            if cursor.eat('h') {
+                cursor.flags(&mut flags);
+                let (_, length) = cursor.duration()?.unwrap_or((None, 0.));
+                cursor.flags(&mut flags);
+                push(
+                    &flags,
+                    SimaiNoteKind::Hold {
+                        button,
+                        duration: length * 1000.,
+                    },
+                );
+            } else if let Some(first_segment) = cursor.shape()? {
+                // 星星头部
+                if !flags.no_star {
+                    push(&flags, SimaiNoteKind::Tap { button, star: true });
+                }
+                let mut next_segment = Some(first_segment);
+                loop {
+                    let mut segments = Vec::new();
+                    let mut slide_flags = Flags::default();
+                    let (mut wait, mut length) = (None, 0.);
+                    while let Some(segment) = next_segment {
+                        segments.push(segment);
+                        cursor.flags(&mut slide_flags);
+                        if let Some((segment_wait, segment_length)) = cursor.duration()? {
+                            wait = wait.or(segment_wait);
+                            length += segment_length;
+                        }
+                        cursor.flags(&mut slide_flags);
+                        next_segment = cursor.shape()?;
+                    }
+                    push(
+                        &slide_flags,
+                        SimaiNoteKind::Slide {
+                            start: button,
+                            segments,
+                            // 默认在头部之后等待一拍再开始滑动
+                            wait: wait.unwrap_or(60. / bpm) * 1000.,
+                            duration: length * 1000.,
+                        },
+                    );
+                    // `*` 表示同一星星的另一条轨迹
+                    if !cursor.eat('*') {
+                        break;
+                    }
+                    next_segment = Some(cursor.shape()?.ok_or_else(|| cursor.invalid())?);
+                }
+            } else {
+                push(
+                    &flags,
+                    SimaiNoteKind::Tap {
+                        button,
+                        star: flags.star,
+                    },
+                );
+            }
+        }
+        'A'..='E' => {
+            let digits = match cursor.peek_at(1) {
+                Some(c) if c.is_ascii_digit() => 2,
+                _ => 1,
+            };
+            let zone = Zone::parse(&text_of(&chars[..digits.min(chars.len())]))
+                .ok_or_else(|| cursor.invalid())?;
+            cursor.pos = digits;
+            cursor.flags(&mut flags);
+            if cursor.eat('h') {
+                cursor.flags(&mut flags);
+                let (_, length) = cursor.duration()?.unwrap_or((None, 0.));
+                cursor.flags(&mut flags);
+                push(
+                    &flags,
+                    SimaiNoteKind::TouchHold {
+                        zone,
+                        duration: length * 1000.,
+                        firework: flags.firework,
+                    },
+                );
+            } else {
+                push(
+                    &flags,
+                    SimaiNoteKind::Touch {
+                        zone,
+                        firework: flags.firework,
+                    },
+                );
+            }
+        }
+        _ => return Err(cursor.invalid()),
+    }
+
+    if cursor.pos < chars.len() {
+        return Err(cursor.invalid());
+    }
+    Ok(())
+}
+
+// 解析两个逗号之间的内容
+fn parse_group(
+    group: &[Char],
+    time: f64,
+    bpm: f64,
+    notes: &mut Vec<SimaiNote>,
+) -> Result<(), ParseError> {
+    for note in group.split(|c| c.c == '/' || c.c == '`') {
+        if note.is_empty() {
+            continue;
+        }
+        // `12` 是 `1/2` 的简写
+        if note.len() > 1 && note.iter().all(|c| matches!(c.c, '1'..='8')) {
+            for c in note {
+                parse_note(std::slice::from_ref(c), time, bpm, notes)?;
+            }
+        } else {
+            parse_note(note, time, bpm, notes)?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_chart(
+    chars: &[Char],
+    wholebpm: Option<f64>,
+    first: f64,
+) -> Result<SimaiChart, ParseError> {
+    let mut notes = Vec::new();
+    let mut bpm = wholebpm;
+    let mut divisor = 4.;
+    // `{#秒}` 形式的固定步长
+    let mut fixed_step: Option<f64> = None;
+    let mut time = first;
+    let mut group: Vec<Char> = Vec::new();
+
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        match ch.c {
+            '(' => {
+                let (content, next) = enclosed(chars, i, ')')?;
+                bpm = Some(content.parse().map_err(|_| {
+                    ch.error(ParseErrorKind::InvalidValue {
+                        field: "bpm",
+                        value: content.clone(),
+                    })
+                })?);
+                i = next;
+                continue;
+            }
+            '{' => {
+                let (content, next) = enclosed(chars, i, '}')?;
+                let invalid = || {
+                    ch.error(ParseErrorKind::InvalidValue {
+                        field: "divisor",
+                        value: content.clone(),
+                    })
+                };
+                match content.strip_prefix('#') {
+                    Some(seconds) => fixed_step = Some(seconds.parse().map_err(|_| invalid())?),
+                    None => {
+                        divisor = content.parse().map_err(|_| invalid())?;
+                        fixed_step = None;
+                    }
+                }
+                i = next;
+                continue;
+            }
+            ',' => {
+                let bpm = bpm.ok_or_else(|| ch.error(ParseErrorKind::MissingBpm))?;
+                if !group.is_empty() {
+                    parse_group(&group, time * 1000., bpm, &mut notes)?;
+                    group.clear();
+                }
+                time += fixed_step.unwrap_or(240. / bpm / divisor);
+            }
+            // 谱面结束标记
+            'E' if group.is_empty() && !chars.get(i + 1).is_some_and(|c| c.c.is_ascii_digit()) => {
+                break;
+            }
+            '[' => {
+                // 时长括号内的字符原样保留
+                let (_, next) = enclosed(chars, i, ']')?;
+                group.extend_from_slice(&chars[i..next]);
+                i = next;
+                continue;
+            }
+            _ => group.push(ch),
+        }
+        i += 1;
+    }
+
+    if !group.is_empty() {
+        let bpm = bpm.ok_or_else(|| group[0].error(ParseErrorKind::MissingBpm))?;
+        parse_group(&group, time * 1000., bpm, &mut notes)?;
+    }
+
+    Ok(SimaiChart { notes })
+}
+
+/// 从文件解析 maidata.txt
+pub fn parse_maidata_file<P: AsRef<Path>>(file_path: P) -> Result<Maidata, ParseError> {
+    let file = File::open(file_path).map_err(|e| ParseError {
+        line: 0,
+        column: 0,
+        kind: ParseErrorKind::Io(e),
+    })?;
+    parse_maidata(file)
+}
+
+/// 从任意数据源解析 maidata.txt
+pub fn parse_maidata<R: Read>(mut reader: R) -> Result<Maidata, ParseError> {
+    let mut content = String::new();
+    reader
+        .read_to_string(&mut content)
+        .map_err(|e| ParseError {
+            line: 0,
+            column: 0,
+            kind: ParseErrorKind::Io(e),
+        })?;
+    parse_maidata_str(&content)
+}
+
+/// 从字符串解析 maidata.txt
+pub fn parse_maidata_str(content: &str) -> Result<Maidata, ParseError> {
+    // (键, 值, 值所在行, 值所在列)
+    let mut fields: Vec<(String, String, usize, usize)> = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim_start_matches('\u{feff}');
+        if let Some(rest) = line.strip_prefix('&') {
+            let (key, value) = rest.split_once('=').ok_or(ParseError {
+                line: index + 1,
+                column: 1,
+                kind: ParseErrorKind::InvalidKeyValue,
+            })?;
+            fields.push((key.to_string(), value.to_string(), index + 1, key.len() + 3));
+        } else if let Some((_, value, _, _)) = fields.last_mut() {
+            value.push('\n');
+            value.push_str(line);
+        }
+    }
+
+    let mut maidata = Maidata::default();
+    let number = |value: &str, field, line, column| {
+        value.trim().parse::<f64>().map_err(|_| ParseError {
+            line,
+            column,
+            kind: ParseErrorKind::InvalidValue {
+                field,
+                value: value.to_string(),
+            },
+        })
+    };
+    let mut inotes = Vec::new();
+    for (key, value, line, column) in fields {
+        match key.as_str() {
+            "title" => maidata.title = value.trim().to_string(),
+            "artist" => maidata.artist = value.trim().to_string(),
+            "des" => maidata.designer = value.trim().to_string(),
+            "wholebpm" => maidata.wholebpm = Some(number(&value, "wholebpm", line, column)?),
+            "first" => maidata.first = number(&value, "first", line, column)?,
+            _ => {
+                let level = |prefix| key.strip_prefix(prefix).and_then(|n| n.parse::<u8>().ok());
+                if let Some(n) = level("lv_") {
+                    maidata.levels.insert(n, value.trim().to_string());
+                } else if let Some(n) = level("inote_") {
+                    inotes.push((n, value, line, column));
+                } else {
+                    maidata.extra.push((key, value.trim().to_string()));
+                }
+            }
+        }
+    }
+
+    // 谱面依赖 wholebpm 与 first，最后解析
+    for (n, value, line, column) in inotes {
+        let chars = chart_chars(&value, line, column);
+        let chart = parse_chart(&chars, maidata.wholebpm, maidata.first)?;
+        maidata.charts.insert(n, chart);
+    }
+
+    Ok(maidata)
+}
+
+// 毫秒转谱面时间
+fn millis(ms: f64) -> ChartTime {
+    ChartTime::microseconds((ms * 1000.) as i64)
+}
+
+impl SimaiChart {
+    /// 将谱面转换为判定组件与渲染组件
+    ///
+    /// 按键对应 `WkrType::Lane(0..8)`，触摸对应 `WkrType::Sensor`，星星轨迹对应 `WkrType::Slide`。
+    /// maimai 的下落速度不随BPM变化，卷轴位置即为时间
+    pub fn build_widgets(
+        &self,
+        profile: &Arc<JudgementProfile>,
+        screen_height: f64,
+        scroll_speed: f64,
+    ) -> (Vec<Widget>, Vec<WidgetForDisplay>) {
+        let mut widgets = Vec::new();
+        let mut widgets_for_display = Vec::new();
+        let approach_distance = screen_height / scroll_speed;
+
+        for (id, note) in self.notes.iter().enumerate() {
+            // 判定时刻与结束时刻（毫秒）
+            let (wkr_type, hit_ms, end_ms) = match &note.kind {
+                SimaiNoteKind::Tap { button, .. } => (WkrType::Lane(button - 1), note.time, None),
+                SimaiNoteKind::Hold { button, duration } => (
+                    WkrType::Lane(button - 1),
+                    note.time,
+                    Some(note.time + duration),
+                ),
+                SimaiNoteKind::Touch { zone, .. } => (WkrType::Sensor(*zone), note.time, None),
+                SimaiNoteKind::TouchHold { zone, duration, .. } => (
+                    WkrType::Sensor(*zone),
+                    note.time,
+                    Some(note.time + duration),
+                ),
+                SimaiNoteKind::Slide { wait, duration, .. } => (
+                    WkrType::Slide,
+                    note.time + wait,
+                    Some(note.time + wait + duration),
+                ),
+            };
+
+            let time_hit = millis(hit_ms);
+            let time_end = end_ms.map(millis);
+            let display_time = millis(hit_ms - approach_distance);
+
+            let kind = match (&note.kind, time_end) {
+                (
+                    SimaiNoteKind::Slide {
+                        start, segments, ..
+                    },
+                    Some(time_end),
+                ) => WidgetKind::Slide {
+                    time_end,
+                    path: slide_path(*start, segments),
+                    progress: 0,
+                },
+                (_, Some(time_end)) => WidgetKind::Hold {
+                    time_end,
+                    state: HoldState::Waiting,
+                },
+                (_, None) => WidgetKind::Tap,
+            };
+
+            let widget = Widget::new(
+                id,
+                time_hit,
+                wkr_type,
+                kind,
+                note.is_break,
+                Arc::clone(profile),
+            );
+            let widget_time = widget.time_stamp;
+            widgets.push(widget);
+
+            widgets_for_display.push(WidgetForDisplay {
+                id,
+                time_stamp_general: widget_time.min(display_time),
+                time_stamp_display: display_time,
+                time_hit,
+                time_end,
+                position: hit_ms,
+                position_end: end_ms,
+                place: wkr_type,
+                is_break: note.is_break,
+                deleted: false,
+            });
+        }
+
+        (widgets, widgets_for_display)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIDATA: &str = "&title=Test Song
+&artist=Someone
+&wholebpm=120
+&first=0.5
+&lv_5=13+
+&inote_5=(120){4}1,2b/3,
+{8}4h[4:1],A1/C,
+|| comment
+1-5[4:1]*>3[8:1],
+E
+";
+
+    #[test]
+    fn parse_headers_and_notes() {
+        let maidata = parse_maidata_str(MAIDATA).unwrap();
+        assert_eq!(maidata.title, "Test Song");
+        assert_eq!(maidata.wholebpm, Some(120.));
+        assert_eq!(maidata.levels[&5], "13+");
+
+        let notes = &maidata.charts[&5].notes;
+        let times: Vec<f64> = notes.iter().map(|n| n.time).collect();
+        // 每拍 500ms，{8} 后每步 250ms
+        assert_eq!(
+            times,
+            [500., 1000., 1000., 1500., 1750., 1750., 2000., 2000., 2000.]
+        );
+        assert!(notes[1].is_break);
+        assert_eq!(
+            notes[3].kind,
+            SimaiNoteKind::Hold {
+                button: 4,
+                duration: 500.
+            }
+        );
+        assert_eq!(
+            notes[5].kind,
+            SimaiNoteKind::Touch {
+                zone: Zone::C,
+                firework: false
+            }
+        );
+        assert_eq!(
+            notes[6].kind,
+            SimaiNoteKind::Tap {
+                button: 1,
+                star: true
+            }
+        );
+        assert!(matches!(
+            notes[8].kind,
+            SimaiNoteKind::Slide {
+                start: 1,
+                wait: 500.,
+                duration: 250.,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn shorthand_each_and_durations() {
+        let chart = "&inote_1=(60){1}18,3h[#1.5],4-6[2##1:1],5-7[90#4:1],\n";
+        let notes = &parse_maidata_str(chart).unwrap().charts[&1].notes;
+        assert_eq!(notes.len(), 7);
+        assert_eq!(
+            notes[2].kind,
+            SimaiNoteKind::Hold {
+                button: 3,
+                duration: 1500.
+            }
+        );
+        assert!(matches!(
+            notes[4].kind,
+            SimaiNoteKind::Slide {
+                wait: 2000.,
+                duration: 4000.,
+                ..
+            }
+        ));
+        assert!(matches!(
+            notes[6].kind,
+            SimaiNoteKind::Slide { duration, .. } if (duration - 2000. / 3.).abs() < 1e-9
+        ));
+    }
+
+    #[test]
+    fn slide_paths() {
+        let straight = SlideSegment {
+            shape: SlideShape::Straight,
+            end: 5,
+        };
+        assert_eq!(
+            slide_path(1, &[straight]),
+            [Zone::A(1), Zone::B(1), Zone::C, Zone::B(5), Zone::A(5)]
+        );
+        let arc = SlideSegment {
+            shape: SlideShape::Right,
+            end: 3,
+        };
+        assert_eq!(slide_path(1, &[arc]), [Zone::A(1), Zone::A(2), Zone::A(3)]);
+    }
+
+    #[test]
+    fn invalid_note_reports_position() {
+        let error = parse_maidata_str("&wholebpm=120\n&inote_1={4}1,\n9,\n").unwrap_err();
+        assert_eq!((error.line, error.column), (3, 1));
+        assert!(matches!(error.kind, ParseErrorKind::InvalidNote(_)));
+    }
+
+    #[test]
+    fn widgets_route_to_workers() {
+        let maidata = parse_maidata_str(MAIDATA).unwrap();
+        let (widgets, _) =
+            maidata.charts[&5].build_widgets(&Arc::new(JudgementProfile::maimai_dx()), 1000., 1.);
+        assert_eq!(widgets[0].wkr_ppty, WkrType::Lane(0));
+        assert_eq!(widgets[4].wkr_ppty, WkrType::Sensor(Zone::A(1)));
+        assert_eq!(widgets[7].wkr_ppty, WkrType::Slide);
+        assert!(widgets[1].is_break);
+    }
+}
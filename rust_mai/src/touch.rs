@@ -0,0 +1,350 @@
+use crate::clock::SharedClock;
+use crate::dev_read::{DeviceFilter, kernel_instant};
+use crate::sensor::{Zone, zone_at};
+use crate::types::{ChartTime, Event, EventType};
+
+use evdev::{AbsoluteAxisCode, Device, SynchronizationCode};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+const EV_SYN: u16 = evdev::EventType::SYNCHRONIZATION.0;
+const EV_ABS: u16 = evdev::EventType::ABSOLUTE.0;
+const SYN_REPORT: u16 = SynchronizationCode::SYN_REPORT.0;
+const ABS_MT_SLOT: u16 = AbsoluteAxisCode::ABS_MT_SLOT.0;
+const ABS_MT_TRACKING_ID: u16 = AbsoluteAxisCode::ABS_MT_TRACKING_ID.0;
+const ABS_MT_POSITION_X: u16 = AbsoluteAxisCode::ABS_MT_POSITION_X.0;
+const ABS_MT_POSITION_Y: u16 = AbsoluteAxisCode::ABS_MT_POSITION_Y.0;
+// 跟踪的触点数上限，更大的 slot 被忽略
+const MAX_SLOTS: usize = 64;
+
+/// 触摸屏的坐标范围，判定圆为其内切圆
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TouchArea {
+    pub min_x: i32,
+    pub max_x: i32,
+    pub min_y: i32,
+    pub max_y: i32,
+}
+
+impl TouchArea {
+    /// 触摸屏坐标换算为以判定圆圆心为原点、半径为1、y轴向上的坐标
+    pub fn to_unit(&self, x: i32, y: i32) -> (f64, f64) {
+        let center_x = (self.min_x + self.max_x) as f64 / 2.;
+        let center_y = (self.min_y + self.max_y) as f64 / 2.;
+        let radius = ((self.max_x - self.min_x).min(self.max_y - self.min_y) as f64 / 2.).max(1.);
+        (
+            (x as f64 - center_x) / radius,
+            (center_y - y as f64) / radius,
+        )
+    }
+
+    fn from_device(device: &Device) -> Option<Self> {
+        let mut x = None;
+        let mut y = None;
+        for (axis, info) in device.get_absinfo().ok()? {
+            match axis.0 {
+                ABS_MT_POSITION_X => x = Some((info.minimum(), info.maximum())),
+                ABS_MT_POSITION_Y => y = Some((info.minimum(), info.maximum())),
+                _ => {}
+            }
+        }
+        let ((min_x, max_x), (min_y, max_y)) = (x?, y?);
+        Some(Self {
+            min_x,
+            max_x,
+            min_y,
+            max_y,
+        })
+    }
+}
+
+// 一个触点（多点触控协议 B 中的一个 slot）
+#[derive(Debug, Clone, Copy, Default)]
+struct Contact {
+    active: bool,
+    x: i32,
+    y: i32,
+}
+
+/// 将多点触控事件转换为各区域的按下与松开
+///
+/// 按 evdev 多点触控协议 B 处理 `ABS_MT_*` 事件，每个 `SYN_REPORT` 时比较各触点所在的区域，
+/// 区域内第一个触点进入时按下，最后一个触点离开时松开。只跟踪前 64 个 slot
+#[derive(Debug, Clone)]
+pub struct TouchTracker {
+    area: TouchArea,
+    // 当前 slot，超出上限时为 None
+    slot: Option<usize>,
+    contacts: Vec<Contact>,
+    touched: BTreeSet<Zone>,
+}
+
+impl TouchTracker {
+    pub fn new(area: TouchArea) -> Self {
+        Self {
+            area,
+            slot: Some(0),
+            contacts: vec![Contact::default()],
+            touched: BTreeSet::new(),
+        }
+    }
+
+    /// 处理一个原始事件，在 `SYN_REPORT` 时返回区域的变化，先松开后按下
+    pub fn feed(&mut self, event_type: u16, code: u16, value: i32) -> Vec<EventType> {
+        match (event_type, code) {
+            (EV_ABS, ABS_MT_SLOT) => {
+                self.slot = usize::try_from(value).ok().filter(|&slot| slot < MAX_SLOTS);
+                if let Some(slot) = self.slot
+                    && self.contacts.len() <= slot
+                {
+                    self.contacts.resize(slot + 1, Contact::default());
+                }
+            }
+            (EV_SYN, SYN_REPORT) => return self.report(),
+            _ => {}
+        }
+        if let Some(slot) = self.slot
+            && event_type == EV_ABS
+        {
+            let contact = &mut self.contacts[slot];
+            match code {
+                ABS_MT_TRACKING_ID => contact.active = value >= 0,
+                ABS_MT_POSITION_X => contact.x = value,
+                ABS_MT_POSITION_Y => contact.y = value,
+                _ => {}
+            }
+        }
+        Vec::new()
+    }
+
+    fn report(&mut self) -> Vec<EventType> {
+        let touched: BTreeSet<Zone> = self
+            .contacts
+            .iter()
+            .filter(|contact| contact.active)
+            .filter_map(|contact| {
+                let (x, y) = self.area.to_unit(contact.x, contact.y);
+                zone_at(x, y)
+            })
+            .collect();
+        let changes = self
+            .touched
+            .difference(&touched)
+            .map(|&zone| EventType::TouchUp(zone))
+            .chain(
+                touched
+                    .difference(&self.touched)
+                    .map(|&zone| EventType::TouchDown(zone)),
+            )
+            .collect();
+        self.touched = touched;
+        changes
+    }
+
+    /// 当前被触摸的区域
+    pub fn touched(&self) -> impl Iterator<Item = Zone> + '_ {
+        self.touched.iter().copied()
+    }
+}
+
+/// 录制的原始事件，`time` 为秒
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawInput {
+    pub time: f64,
+    pub event_type: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// 解析 evtest 的输出
+///
+/// 只读取 `Event: time ...` 行，其中 SYN_REPORT 行按 `EV_SYN`/`SYN_REPORT` 处理
+pub fn parse_recording(text: &str) -> Vec<RawInput> {
+    text.lines()
+        .filter_map(|line| {
+            let rest = line.trim().strip_prefix("Event: time ")?;
+            let (time, rest) = rest.split_once(',')?;
+            let time = time.trim().parse().ok()?;
+            if rest.contains("SYN_REPORT") {
+                return Some(RawInput {
+                    time,
+                    event_type: EV_SYN,
+                    code: SYN_REPORT,
+                    value: 0,
+                });
+            }
+            // 取 `type 3 (EV_ABS)`、`code 53 (...)`、`value 512` 中的数字
+            let field = |name: &str| {
+                rest.split(',')
+                    .map(str::trim)
+                    .find_map(|part| part.strip_prefix(name))
+                    .and_then(|part| part.split_whitespace().next())
+                    .and_then(|number| number.parse::<i64>().ok())
+            };
+            Some(RawInput {
+                time,
+                event_type: field("type ")? as u16,
+                code: field("code ")? as u16,
+                value: field("value ")? as i32,
+            })
+        })
+        .collect()
+}
+
+/// 将录制的事件转换为判定事件，时间以第一个事件为0
+pub fn events_from_recording(area: TouchArea, inputs: &[RawInput]) -> Vec<Event> {
+    let Some(start) = inputs.first().map(|input| input.time) else {
+        return Vec::new();
+    };
+    let mut tracker = TouchTracker::new(area);
+    inputs
+        .iter()
+        .flat_map(|input| {
+            let time_stamp = ChartTime::microseconds(((input.time - start) * 1e6).round() as i64);
+            tracker
+                .feed(input.event_type, input.code, input.value)
+                .into_iter()
+                .map(move |event_ppty| Event {
+                    time_stamp,
+                    event_ppty,
+                })
+        })
+        .collect()
+}
+
+// 查找第一个符合条件的多点触控设备
+fn find_touch_device(filter: &DeviceFilter) -> Option<(Device, TouchArea)> {
+    let mut paths: Vec<_> = fs::read_dir("/dev/input")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.to_string_lossy().contains("event"))
+        .collect();
+    paths.sort();
+    paths.into_iter().find_map(|path| {
+        let device = Device::open(&path).ok()?;
+        let id = device.input_id();
+        if !filter.matches(device.name().unwrap_or(""), id.vendor(), id.product()) {
+            return None;
+        }
+        let area = TouchArea::from_device(&device)?;
+        println!(
+            "监听触摸屏: {} ({})",
+            device.name().unwrap_or("未知设备"),
+            path.display()
+        );
+        Some((device, area))
+    })
+}
+
+// 读取触摸屏，区域的按下与松开以内核时间戳换算的谱面时间发送
+// 游戏主程序目前只有键盘轨道的判定线程，尚未调用此函数
+pub async fn start_touch_listen(
+    sndr: mpsc::Sender<Event>,
+    clock: SharedClock,
+    filter: DeviceFilter,
+) -> Result<JoinHandle<()>, Box<dyn Error>> {
+    let (device, area) = find_touch_device(&filter).ok_or("未找到触摸屏")?;
+    let mut stream = device.into_event_stream()?;
+    Ok(tokio::spawn(async move {
+        let mut tracker = TouchTracker::new(area);
+        while let Ok(event) = stream.next_event().await {
+            let time_stamp = clock.chart_time_at(kernel_instant(event.timestamp()));
+            for event_ppty in tracker.feed(event.event_type().0, event.code(), event.value()) {
+                if sndr
+                    .send(Event {
+                        time_stamp,
+                        event_ppty,
+                    })
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: TouchArea = TouchArea {
+        min_x: 0,
+        max_x: 1080,
+        min_y: 0,
+        max_y: 1080,
+    };
+
+    // 两指触摸：一指按在 A1 后滑到 B1，另一指点按中心
+    const RECORDING: &str = "\
+Event: time 1700000000.000000, type 3 (EV_ABS), code 47 (ABS_MT_SLOT), value 0
+Event: time 1700000000.000000, type 3 (EV_ABS), code 57 (ABS_MT_TRACKING_ID), value 12
+Event: time 1700000000.000000, type 3 (EV_ABS), code 53 (ABS_MT_POSITION_X), value 650
+Event: time 1700000000.000000, type 3 (EV_ABS), code 54 (ABS_MT_POSITION_Y), value 40
+Event: time 1700000000.000000, -------------- SYN_REPORT ------------
+Event: time 1700000000.050000, type 3 (EV_ABS), code 47 (ABS_MT_SLOT), value 1
+Event: time 1700000000.050000, type 3 (EV_ABS), code 57 (ABS_MT_TRACKING_ID), value 13
+Event: time 1700000000.050000, type 3 (EV_ABS), code 53 (ABS_MT_POSITION_X), value 540
+Event: time 1700000000.050000, type 3 (EV_ABS), code 54 (ABS_MT_POSITION_Y), value 560
+Event: time 1700000000.050000, -------------- SYN_REPORT ------------
+Event: time 1700000000.100000, type 3 (EV_ABS), code 47 (ABS_MT_SLOT), value 0
+Event: time 1700000000.100000, type 3 (EV_ABS), code 54 (ABS_MT_POSITION_Y), value 300
+Event: time 1700000000.100000, -------------- SYN_REPORT ------------
+Event: time 1700000000.150000, type 3 (EV_ABS), code 57 (ABS_MT_TRACKING_ID), value -1
+Event: time 1700000000.150000, type 3 (EV_ABS), code 47 (ABS_MT_SLOT), value 1
+Event: time 1700000000.150000, type 3 (EV_ABS), code 57 (ABS_MT_TRACKING_ID), value -1
+Event: time 1700000000.150000, -------------- SYN_REPORT ------------
+";
+
+    #[test]
+    fn recorded_touches_map_to_zones() {
+        let inputs = parse_recording(RECORDING);
+        assert_eq!(inputs.len(), 17);
+        let events: Vec<_> = events_from_recording(AREA, &inputs)
+            .into_iter()
+            .map(|event| (event.time_stamp.num_milliseconds(), event.event_ppty))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (0, EventType::TouchDown(Zone::A(1))),
+                (50, EventType::TouchDown(Zone::C)),
+                (100, EventType::TouchUp(Zone::A(1))),
+                (100, EventType::TouchDown(Zone::B(1))),
+                (150, EventType::TouchUp(Zone::B(1))),
+                (150, EventType::TouchUp(Zone::C)),
+            ]
+        );
+    }
+
+    #[test]
+    fn zone_held_while_any_contact_inside() {
+        let mut tracker = TouchTracker::new(AREA);
+        for slot in 0..2 {
+            tracker.feed(EV_ABS, ABS_MT_SLOT, slot);
+            tracker.feed(EV_ABS, ABS_MT_TRACKING_ID, slot);
+            tracker.feed(EV_ABS, ABS_MT_POSITION_X, 540 + slot);
+            tracker.feed(EV_ABS, ABS_MT_POSITION_Y, 540);
+        }
+        assert_eq!(
+            tracker.feed(EV_SYN, SYN_REPORT, 0),
+            [EventType::TouchDown(Zone::C)]
+        );
+        tracker.feed(EV_ABS, ABS_MT_TRACKING_ID, -1);
+        assert!(tracker.feed(EV_SYN, SYN_REPORT, 0).is_empty());
+        assert_eq!(tracker.touched().collect::<Vec<_>>(), [Zone::C]);
+
+        // 超出上限的 slot 被忽略，不会按其编号分配触点
+        tracker.feed(EV_ABS, ABS_MT_SLOT, i32::MAX);
+        tracker.feed(EV_ABS, ABS_MT_TRACKING_ID, 99);
+        tracker.feed(EV_ABS, ABS_MT_POSITION_X, 650);
+        tracker.feed(EV_ABS, ABS_MT_POSITION_Y, 40);
+        assert!(tracker.feed(EV_SYN, SYN_REPORT, 0).is_empty());
+        assert_eq!(tracker.contacts.len(), 2);
+    }
+}
@@ -1,25 +1,53 @@
-use chrono::{DateTime, Duration, Utc};
+use crate::judgement::JudgementProfile;
+use crate::sensor::Zone;
+use chrono::Duration;
 use general_time_event_driven::types::*;
+use serde::{Deserialize, Serialize};
+
+use std::sync::Arc;
+
+/// 谱面时间，即相对歌曲开头的偏移
+///
+/// 谱面与判定中的时间均使用谱面时间，游玩时由 `PlaySession` 与时钟对应
+pub type ChartTime = Duration;
 
 // 事件类型模块
-#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum EventType {
     OnlyWkr0,
-    D,
-    F,
-    J,
-    K,
+    // 第N轨道（从0开始）按下
+    Press(u8),
+    // 第N轨道（从0开始）松开
+    Release(u8),
+    // 触摸区域按下
+    TouchDown(Zone),
+    // 触摸区域松开
+    TouchUp(Zone),
     All,
 }
 
+impl EventType {
+    // 按下类事件
+    pub fn is_down(&self) -> bool {
+        matches!(self, EventType::Press(_) | EventType::TouchDown(_))
+    }
+
+    // 松开类事件
+    pub fn is_up(&self) -> bool {
+        matches!(self, EventType::Release(_) | EventType::TouchUp(_))
+    }
+}
+
 // Wkr类型模块
-#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum WkrType {
     Wkr0,
-    Wkr1,
-    Wkr2,
-    Wkr3,
-    Wkr4,
+    // 第N轨道（从0开始）的判定线程
+    Lane(u8),
+    // 触摸区域的判定线程
+    Sensor(Zone),
+    // 星星轨迹的判定线程，接收所有区域的触摸
+    Slide,
 }
 
 impl EventTypeTrait for EventType {}
@@ -27,33 +55,122 @@ impl EventTypeTrait for EventType {}
 // Wkr 类型模块，直接复用事件类型模块
 impl WorkerPropertyTrait for WkrType {}
 
-// 返回类型枚举
-#[derive(Debug, Hash)]
+impl WkrType {
+    // 线程的事件处理模式
+    pub fn worker_mode(self) -> WorkerMode {
+        match self {
+            WkrType::Wkr0 | WkrType::Slide => WorkerMode::ProcessMultiTimes,
+            WkrType::Lane(_) | WkrType::Sensor(_) => WorkerMode::ProcessOnce,
+        }
+    }
+
+    // 线程是否接收该类型的事件
+    pub fn accepts(self, event_tp: &EventType) -> bool {
+        match (self, event_tp) {
+            (_, EventType::All) | (WkrType::Wkr0, _) => true,
+            (
+                WkrType::Lane(lane),
+                EventType::Press(event_lane) | EventType::Release(event_lane),
+            ) => lane == *event_lane,
+            (
+                WkrType::Sensor(zone),
+                EventType::TouchDown(event_zone) | EventType::TouchUp(event_zone),
+            ) => zone == *event_zone,
+            (WkrType::Slide, EventType::TouchDown(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+// 为一组Wkr类型构建工作池所需的工作属性列表
+pub fn worker_properties(
+    wkr_types: impl IntoIterator<Item = WkrType>,
+) -> Vec<(WkrType, WorkerMode, BoxedEventSelector<EventType>)> {
+    let mut seen = std::collections::HashSet::new();
+    wkr_types
+        .into_iter()
+        .filter(|wkr_type| seen.insert(*wkr_type))
+        .map(|wkr_type| {
+            (
+                wkr_type,
+                wkr_type.worker_mode(),
+                BuildBoxedEventSelector(move |event_tp: &EventType| wkr_type.accepts(event_tp)),
+            )
+        })
+        .collect()
+}
+
+// 返回类型枚举，从高到低排列
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Judgement {
     CriticalPerfect,
     Perfect,
+    Great,
     Good,
+    Meh,
+}
+
+// 判定对应的音符部位
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NotePart {
+    Tap,
+    HoldHead,
+    HoldTail,
+    Touch,
+    Slide,
 }
 
 // 返回值模块
-#[derive(Debug, Hash)]
+#[derive(Debug, Hash, PartialEq, Eq)]
 pub struct RtV {
     pub is_blank: bool,
     pub id: usize,
     pub judgement: Judgement,
+    pub part: NotePart,
+    // 有符号的时间差（事件时刻 - 目标时刻），为负表示提前（FAST）
+    pub offset: ChartTime,
+    // 音符所在的轨道或触摸区域
+    pub place: WkrType,
+    // 触发判定的事件时刻
+    pub time_stamp: ChartTime,
+}
+
+impl RtV {
+    // 不携带判定的空返回值
+    pub fn blank(id: usize) -> Self {
+        Self {
+            is_blank: true,
+            id,
+            judgement: Judgement::Good,
+            part: NotePart::Tap,
+            offset: ChartTime::zero(),
+            place: WkrType::Wkr0,
+            time_stamp: ChartTime::zero(),
+        }
+    }
+}
+
+// 错过时携带组件的 id、所属线程与判定时刻
+impl ReturnTypeTrait for RtV {
+    type Missed = Miss;
+
+    fn is_blank(&self) -> bool {
+        self.is_blank
+    }
 }
 
-impl ReturnTypeTrait for RtV {}
+// 错过记录
+pub type Miss = MissRecord<usize, WkrType, ChartTime>;
 
 // 事件模块
 #[derive(Debug)]
 pub struct Event {
-    pub time_stamp: DateTime<Utc>,
+    pub time_stamp: ChartTime,
     pub event_ppty: EventType,
 }
 
 impl EventTrait for Event {
-    type TimestampType = DateTime<Utc>;
+    type TimestampType = ChartTime;
     type EventType = EventType;
     type WorkerProperty = WkrType;
     type ReturnType = RtV;
@@ -67,12 +184,119 @@ impl EventTrait for Event {
     }
 }
 
+// 长条的按住状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HoldState {
+    // 等待按下
+    Waiting,
+    // 头部已判定，等待松开
+    Held,
+}
+
+// 判定组件种类
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WidgetKind {
+    Tap,
+    Hold {
+        time_end: ChartTime,
+        state: HoldState,
+    },
+    // 星星轨迹，按顺序经过 `path` 中的区域即完成
+    Slide {
+        time_end: ChartTime,
+        path: Vec<Zone>,
+        progress: usize,
+    },
+}
+
 // 判定组件模块
 #[derive(Debug)]
 pub struct Widget {
     pub id: usize,
-    pub time_stamp: DateTime<Utc>,
+    // 开始接受判定的时刻，即判定时刻减去判定配置中最早的范围
+    pub time_stamp: ChartTime,
+    // 判定时刻
+    pub time_hit: ChartTime,
     pub wkr_ppty: WkrType,
+    pub kind: WidgetKind,
+    pub is_break: bool,
+    // 判定配置，同一谱面的组件共享
+    pub profile: Arc<JudgementProfile>,
+}
+
+impl Widget {
+    pub fn new(
+        id: usize,
+        time_hit: ChartTime,
+        wkr_ppty: WkrType,
+        kind: WidgetKind,
+        is_break: bool,
+        profile: Arc<JudgementProfile>,
+    ) -> Self {
+        Self {
+            id,
+            time_stamp: time_hit - profile.earliest(),
+            time_hit,
+            wkr_ppty,
+            kind,
+            is_break,
+            profile,
+        }
+    }
+
+    fn rtv(&self, judgement: Judgement, part: NotePart, event: &Event, offset: ChartTime) -> RtV {
+        RtV {
+            is_blank: false,
+            id: self.id,
+            judgement,
+            part,
+            offset,
+            place: self.wkr_ppty,
+            time_stamp: event.time_stamp,
+        }
+    }
+
+    // 单点判定对应的部位
+    fn tap_part(&self) -> NotePart {
+        match self.wkr_ppty {
+            WkrType::Sensor(_) => NotePart::Touch,
+            _ => NotePart::Tap,
+        }
+    }
+
+    /// 最晚可判定的时刻，之后的时钟事件会使组件过期
+    pub fn deadline(&self) -> ChartTime {
+        let judged_at = match &self.kind {
+            WidgetKind::Hold {
+                time_end,
+                state: HoldState::Held,
+            }
+            | WidgetKind::Slide { time_end, .. } => *time_end,
+            _ => self.time_hit,
+        };
+        judged_at + self.profile.latest()
+    }
+
+    // 错过，记录的时间见 `miss_time`
+    fn missed(&self) -> RuntimeState<RtV> {
+        RuntimeState::Ready(RuntimeEvent::Missed(self.miss_record(self.id)))
+    }
+
+    // 过期时的判定：按住到结束的长条以最低等级完成，其余为 Miss
+    fn expire(&self, event: &Event) -> RuntimeState<RtV> {
+        match self.kind {
+            WidgetKind::Hold {
+                time_end,
+                state: HoldState::Held,
+            } => RuntimeState::Ready(RuntimeEvent::Some(self.rtv(
+                self.profile.lowest(),
+                NotePart::HoldTail,
+                event,
+                event.time_stamp - time_end,
+            ))),
+            _ => self.missed(),
+        }
+    }
 }
 
 impl WidgetTrait for Widget {
@@ -82,6 +306,11 @@ impl WidgetTrait for Widget {
         self.time_stamp
     }
 
+    // 错过记录判定时刻而非开始接受判定的时刻
+    fn miss_time(&self) -> ChartTime {
+        self.time_hit
+    }
+
     fn get_worker_property(&self) -> <<Self as WidgetTrait>::Event as EventTrait>::WorkerProperty {
         self.wkr_ppty
     }
@@ -90,49 +319,321 @@ impl WidgetTrait for Widget {
         &mut self,
         event: &Self::Event,
     ) -> RuntimeState<<<Self as WidgetTrait>::Event as EventTrait>::ReturnType> {
-        // 计算时间差（使用Duration）
-        let delta = event.time_stamp - self.time_stamp;
+        // 与判定时刻的时间差，判定偏移由判定配置处理
+        let relative_time = event.time_stamp - self.time_hit;
+        let profile = Arc::clone(&self.profile);
+        let grade = |delta| profile.grade(delta);
 
-        // 应用偏移量（原逻辑中的+20ms）
-        let relative_time = delta + Duration::milliseconds(20);
+        let blank = RuntimeState::Pending(RuntimeEvent::Some(RtV::blank(self.id)));
+        // 时钟事件只用于使过期的组件判为 Miss
+        if event.event_ppty == EventType::All {
+            return match event.time_stamp > self.deadline() {
+                true => self.expire(event),
+                false => blank,
+            };
+        }
+        match &mut self.kind {
+            WidgetKind::Tap if event.event_ppty.is_down() => match grade(relative_time) {
+                Some(judgement) => RuntimeState::Ready(RuntimeEvent::Some(self.rtv(
+                    judgement,
+                    self.tap_part(),
+                    event,
+                    relative_time,
+                ))),
+                None => self.missed(),
+            },
+            WidgetKind::Hold { state, .. }
+                if *state == HoldState::Waiting && event.event_ppty.is_down() =>
+            {
+                match grade(relative_time) {
+                    // 头部判定后继续存活，等待松开
+                    Some(judgement) => {
+                        *state = HoldState::Held;
+                        RuntimeState::Pending(RuntimeEvent::Some(self.rtv(
+                            judgement,
+                            NotePart::HoldHead,
+                            event,
+                            relative_time,
+                        )))
+                    }
+                    None => self.missed(),
+                }
+            }
+            WidgetKind::Hold { time_end, state }
+                if *state == HoldState::Held && event.event_ppty.is_up() =>
+            {
+                let release_time = event.time_stamp - *time_end;
+                match grade(release_time) {
+                    Some(judgement) => RuntimeState::Ready(RuntimeEvent::Some(self.rtv(
+                        judgement,
+                        NotePart::HoldTail,
+                        event,
+                        release_time,
+                    ))),
+                    // 松开过早
+                    None if release_time < Duration::zero() => self.missed(),
+                    // 松开过晚
+                    None => RuntimeState::Ready(RuntimeEvent::Some(self.rtv(
+                        profile.lowest(),
+                        NotePart::HoldTail,
+                        event,
+                        release_time,
+                    ))),
+                }
+            }
+            WidgetKind::Slide {
+                time_end,
+                path,
+                progress,
+            } => {
+                let EventType::TouchDown(zone) = event.event_ppty else {
+                    return blank;
+                };
+                // 允许跳过一个区域
+                if path.get(*progress) == Some(&zone) {
+                    *progress += 1;
+                } else if path.get(*progress + 1) == Some(&zone) {
+                    *progress += 2;
+                } else {
+                    return blank;
+                }
+                if *progress < path.len() {
+                    return blank;
+                }
+                let finish_time = event.time_stamp - *time_end;
+                match grade(finish_time) {
+                    Some(judgement) => RuntimeState::Ready(RuntimeEvent::Some(self.rtv(
+                        judgement,
+                        NotePart::Slide,
+                        event,
+                        finish_time,
+                    ))),
+                    // 划得过快
+                    None if finish_time < Duration::zero() => {
+                        RuntimeState::Ready(RuntimeEvent::Some(self.rtv(
+                            profile.lowest(),
+                            NotePart::Slide,
+                            event,
+                            finish_time,
+                        )))
+                    }
+                    None => self.missed(),
+                }
+            }
+            // 与当前状态无关的事件，组件保持不变
+            _ => blank,
+        }
+    }
+}
 
-        // 定义判定范围
-        let critical_perfect_range = Duration::milliseconds(-1)..=Duration::milliseconds(1);
-        let perfect_range = Duration::milliseconds(-5)..=Duration::milliseconds(5);
-        let good_range = Duration::milliseconds(-1000)..=Duration::milliseconds(1000);
+// 渲染组件模块
+#[derive(Debug)]
+pub struct WidgetForDisplay {
+    pub id: usize,
+    pub time_stamp_general: ChartTime,
+    pub time_stamp_display: ChartTime,
+    pub time_hit: ChartTime,
+    // 长条尾部时间
+    pub time_end: Option<ChartTime>,
+    // 卷轴位置，见 ScrollMap
+    pub position: f64,
+    pub position_end: Option<f64>,
+    pub place: WkrType,
+    pub is_break: bool,
+    pub deleted: bool,
+}
 
-        // 进行判定
-        if critical_perfect_range.contains(&relative_time) {
-            RuntimeState::Ready(RuntimeEvent::Some(RtV {
-                is_blank: false,
-                id: self.id,
+/// 毫秒数表示的谱面时间，测试用
+#[cfg(test)]
+pub(crate) fn ms(ms: i64) -> ChartTime {
+    ChartTime::milliseconds(ms)
+}
+
+// 各模块测试共用的组件构造
+#[cfg(test)]
+impl Widget {
+    /// `at_ms` 毫秒时判定的组件
+    pub(crate) fn at(
+        id: usize,
+        at_ms: i64,
+        wkr_ppty: WkrType,
+        kind: WidgetKind,
+        profile: &Arc<JudgementProfile>,
+    ) -> Self {
+        Self::new(id, ms(at_ms), wkr_ppty, kind, false, Arc::clone(profile))
+    }
+
+    /// `at_ms` 毫秒时判定的单点
+    pub(crate) fn tap_at(
+        id: usize,
+        at_ms: i64,
+        wkr_ppty: WkrType,
+        profile: &Arc<JudgementProfile>,
+    ) -> Self {
+        Self::at(id, at_ms, wkr_ppty, WidgetKind::Tap, profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(time_stamp: ChartTime, event_ppty: EventType) -> Event {
+        Event {
+            time_stamp,
+            event_ppty,
+        }
+    }
+
+    // OD5 的长条：MAX ±16ms，300 ±49ms，最宽 ±136ms
+    fn hold(time_hit: ChartTime, time_end: ChartTime, state: HoldState) -> Widget {
+        Widget::at(
+            7,
+            time_hit.num_milliseconds(),
+            WkrType::Lane(0),
+            WidgetKind::Hold { time_end, state },
+            &Arc::new(JudgementProfile::osu_mania(5.)),
+        )
+    }
+
+    #[test]
+    fn hold_judges_head_then_tail() {
+        let base = ChartTime::milliseconds(5000);
+        let mut widget = hold(
+            base,
+            base + Duration::milliseconds(2000),
+            HoldState::Waiting,
+        );
+        assert_eq!(widget.time_stamp, base - Duration::milliseconds(136));
+
+        // 松开事件不影响未按下的长条
+        let state = widget.judge(&event(base, EventType::Release(0)));
+        assert!(matches!(
+            state,
+            RuntimeState::Pending(RuntimeEvent::Some(RtV { is_blank: true, .. }))
+        ));
+
+        let state = widget.judge(&event(
+            base - Duration::milliseconds(10),
+            EventType::Press(0),
+        ));
+        assert!(matches!(
+            state,
+            RuntimeState::Pending(RuntimeEvent::Some(RtV {
+                part: NotePart::HoldHead,
                 judgement: Judgement::CriticalPerfect,
+                place: WkrType::Lane(0),
+                ..
             }))
-        } else if perfect_range.contains(&relative_time) {
+        ));
+        if let RuntimeState::Pending(RuntimeEvent::Some(rtv)) = state {
+            assert_eq!(rtv.offset, -Duration::milliseconds(10));
+            assert_eq!(rtv.time_stamp, base - Duration::milliseconds(10));
+        }
+
+        let state = widget.judge(&event(
+            base + Duration::milliseconds(2030),
+            EventType::Release(0),
+        ));
+        assert!(matches!(
+            state,
             RuntimeState::Ready(RuntimeEvent::Some(RtV {
-                is_blank: false,
-                id: self.id,
+                part: NotePart::HoldTail,
                 judgement: Judgement::Perfect,
+                ..
             }))
-        } else if good_range.contains(&relative_time) {
-            RuntimeState::Ready(RuntimeEvent::Some(RtV {
-                is_blank: false,
-                id: self.id,
-                judgement: Judgement::Good,
+        ));
+        if let RuntimeState::Ready(RuntimeEvent::Some(rtv)) = state {
+            // 尾部的时间差相对于长条结束时刻
+            assert_eq!(rtv.offset, Duration::milliseconds(30));
+        }
+    }
+
+    #[test]
+    fn hold_early_release_misses_tail() {
+        let base = ChartTime::milliseconds(5000);
+        let mut widget = hold(base, base + Duration::milliseconds(3000), HoldState::Held);
+        let state = widget.judge(&event(
+            base + Duration::milliseconds(1000),
+            EventType::Release(0),
+        ));
+        assert!(matches!(
+            state,
+            RuntimeState::Ready(RuntimeEvent::Missed(MissRecord {
+                id: 7,
+                worker_property: WkrType::Lane(0),
+                ..
             }))
-        } else {
-            RuntimeState::Ready(RuntimeEvent::Missed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn clock_ticks_expire_unhit_notes() {
+        use general_time_event_driven::worker_pool::WorkerPool;
+
+        let profile = Arc::new(JudgementProfile::osu_mania(5.));
+        let widgets = (0..3)
+            .map(|id| Widget::tap_at(id, 1000 * id as i64, WkrType::Lane(id as u8 % 2), &profile))
+            .collect();
+        let (rt_sndr, mut rt_rcvr) = tokio::sync::mpsc::channel(100);
+        let (event_sndr, _pool) = WorkerPool::build(
+            worker_properties([WkrType::Lane(0), WkrType::Lane(1)]),
+            widgets,
+            rt_sndr,
+        )
+        .await;
+
+        // 只有时钟事件，没有任何输入
+        for ms in [500, 1200, 2100] {
+            event_sndr
+                .send(event(ChartTime::milliseconds(ms), EventType::All))
+                .await;
         }
+
+        let mut missed = Vec::new();
+        while missed.len() < 2 {
+            if let RuntimeEvent::Missed(miss) = rt_rcvr.recv().await.unwrap() {
+                assert_eq!(
+                    miss.time_stamp,
+                    ChartTime::milliseconds(1000 * miss.id as i64)
+                );
+                missed.push(miss.id);
+            }
+        }
+        missed.sort();
+        // 2000ms 的音符在 2100ms 时仍在判定范围内
+        assert_eq!(missed, [0, 1]);
     }
-}
 
-// 渲染组件模块
-#[derive(Debug)]
-pub struct WidgetForDisplay {
-    pub id: usize,
-    pub time_stamp_general: DateTime<Utc>,
-    pub time_stamp_display: DateTime<Utc>,
-    pub time_hit: DateTime<Utc>,
-    pub place: WkrType,
-    pub deleted: bool,
+    #[tokio::test]
+    async fn clock_ticks_pass_held_hold() {
+        use general_time_event_driven::worker_pool::WorkerPool;
+
+        let profile = Arc::new(JudgementProfile::osu_mania(5.));
+        let widgets = vec![
+            Widget::at(
+                0,
+                1000,
+                WkrType::Lane(0),
+                WidgetKind::Hold {
+                    time_end: ms(3000),
+                    state: HoldState::Held,
+                },
+                &profile,
+            ),
+            Widget::tap_at(1, 2100, WkrType::Lane(0), &profile),
+        ];
+        let (rt_sndr, mut rt_rcvr) = tokio::sync::mpsc::channel(100);
+        let (event_sndr, _pool) =
+            WorkerPool::build(worker_properties([WkrType::Lane(0)]), widgets, rt_sndr).await;
+
+        // 按住中的长条尚未过期，排在其后的音符仍由时钟判为 Miss
+        event_sndr.send(event(ms(2400), EventType::All)).await;
+        loop {
+            match rt_rcvr.recv().await.unwrap() {
+                RuntimeEvent::Missed(miss) => break assert_eq!(miss.id, 1),
+                RuntimeEvent::Some(rtv) => assert!(rtv.is_blank),
+            }
+        }
+    }
 }
@@ -0,0 +1,170 @@
+use crate::beatmap::*;
+
+use std::io::{self, Write};
+
+/// 将谱面写为 .osu 文件
+///
+/// 写出的文件可由 `parse_osu` 重新解析为相同的谱面
+pub fn write_osu<W: Write>(beatmap: &Beatmap, mut w: W) -> io::Result<()> {
+    writeln!(w, "osu file format v{}", beatmap.format_version)?;
+
+    let general = &beatmap.general;
+    writeln!(w, "\n[General]")?;
+    writeln!(w, "AudioFilename: {}", general.audio_filename)?;
+    writeln!(w, "AudioLeadIn: {}", general.audio_lead_in)?;
+    writeln!(w, "PreviewTime: {}", general.preview_time)?;
+    writeln!(w, "Countdown: {}", general.countdown)?;
+    writeln!(w, "SampleSet: {}", general.sample_set)?;
+    writeln!(w, "StackLeniency: {}", general.stack_leniency)?;
+    writeln!(w, "Mode: {}", general.mode)?;
+    writeln!(
+        w,
+        "LetterboxInBreaks: {}",
+        general.letterbox_in_breaks as u8
+    )?;
+    writeln!(w, "SpecialStyle: {}", general.special_style as u8)?;
+    writeln!(
+        w,
+        "WidescreenStoryboard: {}",
+        general.widescreen_storyboard as u8
+    )?;
+    for (key, value) in &general.extra {
+        writeln!(w, "{key}: {value}")?;
+    }
+
+    let metadata = &beatmap.metadata;
+    writeln!(w, "\n[Metadata]")?;
+    writeln!(w, "Title:{}", metadata.title)?;
+    writeln!(w, "TitleUnicode:{}", metadata.title_unicode)?;
+    writeln!(w, "Artist:{}", metadata.artist)?;
+    writeln!(w, "ArtistUnicode:{}", metadata.artist_unicode)?;
+    writeln!(w, "Creator:{}", metadata.creator)?;
+    writeln!(w, "Version:{}", metadata.version)?;
+    writeln!(w, "Source:{}", metadata.source)?;
+    writeln!(w, "Tags:{}", metadata.tags.join(" "))?;
+    if let Some(id) = metadata.beatmap_id {
+        writeln!(w, "BeatmapID:{id}")?;
+    }
+    if let Some(id) = metadata.beatmap_set_id {
+        writeln!(w, "BeatmapSetID:{id}")?;
+    }
+
+    let difficulty = &beatmap.difficulty;
+    writeln!(w, "\n[Difficulty]")?;
+    writeln!(w, "HPDrainRate:{}", difficulty.hp_drain_rate)?;
+    writeln!(w, "CircleSize:{}", difficulty.circle_size)?;
+    writeln!(w, "OverallDifficulty:{}", difficulty.overall_difficulty)?;
+    writeln!(w, "ApproachRate:{}", difficulty.approach_rate)?;
+    writeln!(w, "SliderMultiplier:{}", difficulty.slider_multiplier)?;
+    writeln!(w, "SliderTickRate:{}", difficulty.slider_tick_rate)?;
+
+    writeln!(w, "\n[Events]")?;
+    for event in &beatmap.events {
+        match event {
+            BeatmapEvent::Background { filename, x, y } => {
+                writeln!(w, "0,0,\"{filename}\",{x},{y}")?
+            }
+            BeatmapEvent::Video {
+                start_time,
+                filename,
+                x,
+                y,
+            } => writeln!(w, "Video,{start_time},\"{filename}\",{x},{y}")?,
+            BeatmapEvent::Break {
+                start_time,
+                end_time,
+            } => writeln!(w, "2,{start_time},{end_time}")?,
+            BeatmapEvent::Other(line) => writeln!(w, "{line}")?,
+        }
+    }
+
+    writeln!(w, "\n[TimingPoints]")?;
+    for point in &beatmap.timing_points {
+        writeln!(
+            w,
+            "{},{},{},{},{},{},{},{}",
+            point.time,
+            point.beat_length,
+            point.meter,
+            point.sample_set,
+            point.sample_index,
+            point.volume,
+            point.uninherited as u8,
+            point.effects
+        )?;
+    }
+
+    writeln!(w, "\n[HitObjects]")?;
+    for object in &beatmap.hit_objects {
+        write!(
+            w,
+            "{},{},{},{},{},",
+            object.x,
+            object.y,
+            object.time,
+            object.type_bits(),
+            object.hit_sound
+        )?;
+        let sample = &object.hit_sample;
+        let sample = format!(
+            "{}:{}:{}:{}:{}",
+            sample.normal_set, sample.addition_set, sample.index, sample.volume, sample.filename
+        );
+        match &object.kind {
+            HitObjectKind::Circle => writeln!(w, "{sample}")?,
+            // 只有 edgeSounds 与 edgeSets 俱全时，其后的字段才是音效
+            HitObjectKind::Slider { params } if params.split(',').count() >= 5 => {
+                writeln!(w, "{params},{sample}")?
+            }
+            HitObjectKind::Slider { params } => writeln!(w, "{params}")?,
+            HitObjectKind::Spinner { end_time } => writeln!(w, "{end_time},{sample}")?,
+            // 长条的结束时间与音效之间以 `:` 分隔
+            HitObjectKind::Hold { end_time } => writeln!(w, "{end_time}:{sample}")?,
+        }
+    }
+
+    Ok(())
+}
+
+/// 将谱面写为 .osu 文本
+pub fn to_osu_string(beatmap: &Beatmap) -> String {
+    let mut buffer = Vec::new();
+    write_osu(beatmap, &mut buffer).expect("写入内存不会失败");
+    String::from_utf8(buffer).expect("谱面内容均为UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_osu_str;
+
+    #[test]
+    fn osu_round_trip() {
+        let beatmap = parse_osu_str(include_str!("bin/test.txt")).unwrap();
+        let written = to_osu_string(&beatmap);
+        let reparsed = parse_osu_str(&written).unwrap();
+        assert_eq!(reparsed, beatmap);
+
+        // 判定组件的时间与轨道保持不变
+        let placement = |beatmap: &Beatmap| -> Vec<_> {
+            let (widgets, _) =
+                beatmap.build_widgets(&beatmap.judgement_profile().into(), 1000., 1.);
+            widgets
+                .into_iter()
+                .map(|w| (w.time_hit, w.wkr_ppty, w.kind))
+                .collect()
+        };
+        assert_eq!(placement(&reparsed), placement(&beatmap));
+    }
+
+    #[test]
+    fn short_slider_round_trip() {
+        let content = "osu file format v14\n[HitObjects]\n\
+                       64,192,1000,2,0,B|100:100,1,100\n\
+                       64,192,2000,2,0,B|100:100,1,100,2|0,0:0|0:0,1:2:0:0:\n";
+        let beatmap = parse_osu_str(content).unwrap();
+        let written = to_osu_string(&beatmap);
+        assert!(written.contains("64,192,1000,2,0,B|100:100,1,100\n"));
+        assert_eq!(parse_osu_str(&written).unwrap(), beatmap);
+    }
+}